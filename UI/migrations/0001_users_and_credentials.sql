@@ -0,0 +1,15 @@
+-- Baseline schema, as created by create_tables in the first versions of Bioguard.
+-- The statements use IF NOT EXISTS so users.db files created before migrations are adopted as they are.
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL UNIQUE,
+    fingerprint_image BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS credentials (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    site TEXT NOT NULL,
+    site_username TEXT NOT NULL,
+    site_password TEXT NOT NULL
+);
@@ -0,0 +1,15 @@
+-- One template per enrolled finger
+CREATE TABLE IF NOT EXISTS templates (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    finger TEXT NOT NULL,
+    template BLOB NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Users registered before per-finger templates keep their print as an unlabelled template
+INSERT INTO templates (user_id, finger, template)
+SELECT id, 'unknown', fingerprint_image FROM users
+WHERE length(fingerprint_image) > 0 AND id NOT IN (SELECT user_id FROM templates);
+
+UPDATE users SET fingerprint_image = X'' WHERE id IN (SELECT user_id FROM templates);
@@ -0,0 +1,8 @@
+-- Minutia-triplet keys narrowing the candidates of 1:N identification
+CREATE TABLE IF NOT EXISTS template_index (
+    template_id INTEGER NOT NULL,
+    key INTEGER NOT NULL,
+    FOREIGN KEY (template_id) REFERENCES templates(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS template_index_key ON template_index (key);
@@ -0,0 +1,16 @@
+-- Fuzzy extractor helper data and vault key wrapped with the key it releases
+CREATE TABLE IF NOT EXISTS key_release (
+    template_id INTEGER PRIMARY KEY,
+    helper BLOB NOT NULL,
+    wrapped_key BLOB NOT NULL,
+    FOREIGN KEY (template_id) REFERENCES templates(id) ON DELETE CASCADE
+);
+
+-- Master password and recovery key, both optional
+CREATE TABLE IF NOT EXISTS vault_recovery (
+    user_id INTEGER PRIMARY KEY,
+    password_salt BLOB,
+    password_wrapped_key BLOB,
+    recovery_wrapped_key BLOB,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,20 @@
+-- Credentials reference users.id instead of the free text username, rows are deleted with their user.
+-- Rows whose username matches no user were unreachable and are dropped.
+CREATE TABLE credentials_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    site TEXT NOT NULL,
+    site_username BLOB NOT NULL,
+    site_password BLOB NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+INSERT INTO credentials_new (id, user_id, site, site_username, site_password)
+SELECT credentials.id, users.id, credentials.site, credentials.site_username, credentials.site_password
+FROM credentials JOIN users ON users.username = credentials.username;
+
+DROP TABLE credentials;
+
+ALTER TABLE credentials_new RENAME TO credentials;
+
+CREATE INDEX credentials_user_id ON credentials (user_id);
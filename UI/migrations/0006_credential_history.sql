@@ -0,0 +1,11 @@
+-- Previous passwords of a credential, encrypted like credentials.site_password.
+-- changed_at is the unix time at which the password was replaced.
+CREATE TABLE IF NOT EXISTS credential_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    credential_id INTEGER NOT NULL,
+    site_password BLOB NOT NULL,
+    changed_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS credential_history_credential_id ON credential_history (credential_id);
@@ -0,0 +1,12 @@
+-- Metadata of the credentials. url and notes are encrypted with the vault key like the
+-- account fields, NULL meaning empty. Timestamps are unix times, rows saved by older
+-- versions get the migration time as creation and modification time.
+ALTER TABLE credentials ADD COLUMN url BLOB;
+ALTER TABLE credentials ADD COLUMN notes BLOB;
+ALTER TABLE credentials ADD COLUMN tags TEXT NOT NULL DEFAULT '';
+ALTER TABLE credentials ADD COLUMN favourite INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE credentials ADD COLUMN created_at INTEGER;
+ALTER TABLE credentials ADD COLUMN modified_at INTEGER;
+ALTER TABLE credentials ADD COLUMN last_used_at INTEGER;
+
+UPDATE credentials SET created_at = strftime('%s', 'now'), modified_at = strftime('%s', 'now') WHERE created_at IS NULL;
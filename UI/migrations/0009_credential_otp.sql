@@ -0,0 +1,2 @@
+-- otpauth:// URI of the 2FA secret of a credential, encrypted with the vault key, NULL when none
+ALTER TABLE credentials ADD COLUMN otp BLOB;
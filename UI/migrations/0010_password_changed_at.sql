@@ -0,0 +1,9 @@
+-- Last change of the password itself, modified_at also moves when only the metadata is
+-- edited. Existing rows take their latest archived password change, or their creation.
+ALTER TABLE credentials ADD COLUMN password_changed_at INTEGER;
+
+UPDATE credentials SET password_changed_at = COALESCE(
+    (SELECT MAX(changed_at) FROM credential_history WHERE credential_history.credential_id = credentials.id),
+    created_at,
+    strftime('%s', 'now')
+);
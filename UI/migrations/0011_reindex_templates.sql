@@ -0,0 +1,4 @@
+-- The index keys are now made from ridge endings and bifurcations, the keys stored so far
+-- never match a probe anymore. Each template is indexed again at the next login with its
+-- username, the templates still stored raw at the next start.
+DELETE FROM template_index;
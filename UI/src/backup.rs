@@ -0,0 +1,400 @@
+//Encrypted backup of the vault of one user in a single versioned file, the layout is described
+//in BACKUP_FORMAT.md. The file is protected by a backup password stretched with Argon2id or by
+//the recovery key of the user, never by the fingerprint, so it can be restored on another
+//machine. AES-256-GCM authenticates the header with the content: a damaged or edited file is
+//refused as a whole, nothing of it is restored.
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::health;
+use crate::importer;
+use crate::models::{Credential, Item, ItemContent, PasswordHistory};
+use crate::recovery;
+use crate::totp;
+use crate::vault::VaultKey;
+
+const MAGIC: &[u8; 4] = b"BGBK";
+//Version of the file layout and of the JSON content, read back by every later version
+pub const VERSION: u16 = 1;
+const PROTECTION_PASSWORD: u8 = 1;
+const PROTECTION_RECOVERY_KEY: u8 = 2;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+//Magic, version, protection, salt and the Argon2id cost, all authenticated with the content
+const HEADER_LEN: usize = 4 + 2 + 1 + SALT_LEN + 4 + 4 + 4;
+//A file asking for more Argon2id memory than 1 GiB, more than 16 passes or 16 lanes is refused
+//instead of exhausting the machine
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_PASSES: u32 = 16;
+const MAX_LANES: u32 = 16;
+
+//Secret typed to write or read a backup
+#[derive(Debug, Clone, Copy)]
+pub enum Secret<'a> {
+    Password(&'a str),
+    RecoveryKey(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestoreMode {
+    //Adds what the vault does not have yet, the stored credentials and items are kept
+    Merge,
+    //Deletes the credentials and items of the user first
+    Replace,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u16,
+    pub username: String,
+    //Unix time
+    pub created_at: u64,
+    pub credentials: Vec<BackupCredential>,
+    pub items: Vec<BackupItem>,
+}
+
+//Dates are UTC "YYYY-MM-DD HH:MM:SS" like in Credential, empty when unknown
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupCredential {
+    pub site: String,
+    pub site_username: String,
+    pub site_password: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub tags: String,
+    #[serde(default)]
+    pub favourite: bool,
+    #[serde(default)]
+    pub otp: String,
+    #[serde(default)]
+    pub created_at: String,
+    #[serde(default)]
+    pub modified_at: String,
+    #[serde(default)]
+    pub last_used_at: String,
+    #[serde(default)]
+    pub password_changed_at: String,
+    //Previous passwords, newest first
+    #[serde(default)]
+    pub history: Vec<BackupHistory>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupHistory {
+    pub site_password: String,
+    pub changed_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupItem {
+    pub title: String,
+    pub content: ItemContent,
+}
+
+impl BackupCredential {
+    fn new(credential: &Credential, history: &[PasswordHistory]) -> BackupCredential {
+        BackupCredential {
+            site: credential.site.clone(),
+            site_username: credential.site_username.clone(),
+            site_password: credential.site_password.clone(),
+            url: credential.url.clone(),
+            notes: credential.notes.clone(),
+            tags: credential.tags.clone(),
+            favourite: credential.favourite,
+            otp: credential.otp.clone(),
+            created_at: credential.created_at.clone(),
+            modified_at: credential.modified_at.clone(),
+            last_used_at: credential.last_used_at.clone(),
+            password_changed_at: credential.password_changed_at.clone(),
+            history: history
+                .iter()
+                .map(|entry| BackupHistory { site_password: entry.site_password.clone(), changed_at: entry.changed_at.clone() })
+                .collect(),
+        }
+    }
+
+    //Credential and password history as saved by restore_vault, without ids
+    pub fn to_models(&self) -> (Credential, Vec<PasswordHistory>) {
+        let credential = Credential {
+            site: self.site.clone(),
+            site_username: self.site_username.clone(),
+            site_password: self.site_password.clone(),
+            url: self.url.clone(),
+            notes: self.notes.clone(),
+            tags: self.tags.clone(),
+            favourite: self.favourite,
+            otp: self.otp.clone(),
+            created_at: self.created_at.clone(),
+            modified_at: self.modified_at.clone(),
+            last_used_at: self.last_used_at.clone(),
+            password_changed_at: self.password_changed_at.clone(),
+            ..Credential::default()
+        };
+        let history = self
+            .history
+            .iter()
+            .map(|entry| PasswordHistory { id: 0, credential_id: 0, site_password: entry.site_password.clone(), changed_at: entry.changed_at.clone() })
+            .collect();
+        (credential, history)
+    }
+}
+
+impl BackupItem {
+    pub fn to_item(&self) -> Item {
+        Item { id: 0, title: self.title.clone(), content: self.content.clone() }
+    }
+}
+
+impl Backup {
+    pub fn new(username: &str, credentials: &[(Credential, Vec<PasswordHistory>)], items: &[Item], now: u64) -> Backup {
+        Backup {
+            version: VERSION,
+            username: username.to_string(),
+            created_at: now,
+            credentials: credentials.iter().map(|(credential, history)| BackupCredential::new(credential, history)).collect(),
+            items: items.iter().map(|item| BackupItem { title: item.title.clone(), content: item.content.clone() }).collect(),
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "Backup of {} made on {} UTC: {} credentials with {} previous passwords, {} items",
+            self.username,
+            health::format_utc(self.created_at),
+            self.credentials.len(),
+            self.credentials.iter().map(|credential| credential.history.len()).sum::<usize>(),
+            self.items.len()
+        )
+    }
+
+    //Content checks run before anything is restored, the first problem found is returned
+    pub fn check(&self) -> Result<(), String> {
+        if self.version == 0 || self.version > VERSION {
+            return Err(format!("Backup content version {} is not supported", self.version));
+        }
+        let date = |value: &str| value.is_empty() || health::parse_utc(value).is_some();
+        for (index, credential) in self.credentials.iter().enumerate() {
+            let name = format!("Credential {} ({})", index + 1, credential.site);
+            if credential.site.trim().is_empty() {
+                return Err(format!("Credential {} has no site", index + 1));
+            }
+            if credential.site_password.is_empty() {
+                return Err(format!("{} has no password", name));
+            }
+            if !credential.otp.is_empty() && totp::parse(&credential.otp).is_none() {
+                return Err(format!("{} has an unreadable 2FA secret", name));
+            }
+            let dates = [&credential.created_at, &credential.modified_at, &credential.last_used_at, &credential.password_changed_at];
+            if !dates.iter().all(|value| date(value)) || !credential.history.iter().all(|entry| !entry.changed_at.is_empty() && date(&entry.changed_at)) {
+                return Err(format!("{} has an invalid date", name));
+            }
+        }
+        if let Some(index) = self.items.iter().position(|item| item.title.trim().is_empty()) {
+            return Err(format!("Item {} has no title", index + 1));
+        }
+        Ok(())
+    }
+}
+
+//What a restore writes, the backup entries already in the vault are only counted in merge mode
+pub struct Restore {
+    pub credentials: Vec<(Credential, Vec<PasswordHistory>)>,
+    pub items: Vec<Item>,
+    pub skipped: usize,
+}
+
+//In merge mode a credential is in the vault when the site and the account match (see
+//importer::is_duplicate) and an item when the kind and the title match
+pub fn plan(backup: &Backup, mode: RestoreMode, credentials: &[Credential], items: &[Item]) -> Restore {
+    let mut restore = Restore { credentials: Vec::new(), items: Vec::new(), skipped: 0 };
+    let mut known: Vec<Credential> = match mode {
+        RestoreMode::Merge => credentials.to_vec(),
+        RestoreMode::Replace => Vec::new(),
+    };
+    for entry in &backup.credentials {
+        let (credential, history) = entry.to_models();
+        if mode == RestoreMode::Merge && importer::is_duplicate(&credential, &known) {
+            restore.skipped += 1;
+            continue;
+        }
+        known.push(credential.clone());
+        restore.credentials.push((credential, history));
+    }
+
+    let mut titles: Vec<(String, String)> = match mode {
+        RestoreMode::Merge => items.iter().map(|item| (item.content.kind().as_str().to_string(), item.title.to_lowercase())).collect(),
+        RestoreMode::Replace => Vec::new(),
+    };
+    for entry in &backup.items {
+        let title = (entry.content.kind().as_str().to_string(), entry.title.to_lowercase());
+        if mode == RestoreMode::Merge && titles.contains(&title) {
+            restore.skipped += 1;
+            continue;
+        }
+        titles.push(title);
+        restore.items.push(entry.to_item());
+    }
+    restore
+}
+
+//The recovery key already holds 160 random bits, it is only hashed with the salt of the file
+//so the backup is not encrypted with the key wrapping the vault key
+fn recovery_file_key(recovery_key: &str, salt: &[u8]) -> Option<VaultKey> {
+    let key = recovery::recovery_key(recovery_key)?;
+    let mut hasher = Sha256::new();
+    hasher.update(b"bioguard-backup");
+    hasher.update(key.as_bytes());
+    hasher.update(salt);
+    VaultKey::from_bytes(&hasher.finalize())
+}
+
+fn u32_at(file: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn seal(backup: &Backup, secret: Secret) -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let (protection, cost, key) = match secret {
+        Secret::Password(password) => {
+            let cost = [recovery::ARGON2_MEMORY_KIB, recovery::ARGON2_PASSES, recovery::ARGON2_LANES];
+            (PROTECTION_PASSWORD, cost, recovery::stretch(password, &salt, cost[0], cost[1], cost[2]))
+        }
+        Secret::RecoveryKey(recovery_key) => (PROTECTION_RECOVERY_KEY, [0, 0, 0], recovery_file_key(recovery_key, &salt)),
+    };
+    let key = key.ok_or("Not a valid recovery key")?;
+
+    let mut file = Vec::with_capacity(HEADER_LEN + NONCE_LEN);
+    file.extend_from_slice(MAGIC);
+    file.extend_from_slice(&VERSION.to_le_bytes());
+    file.push(protection);
+    file.extend_from_slice(&salt);
+    for value in cost {
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let json = serde_json::to_vec(backup).map_err(|e| format!("Failed to serialize the backup: {}", e))?;
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = Aes256Gcm::new(key.as_bytes().into());
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: &json, aad: &file })
+        .map_err(|_| "Failed to encrypt the backup".to_string())?;
+    file.extend_from_slice(&nonce);
+    file.extend_from_slice(&ciphertext);
+    Ok(file)
+}
+
+pub fn open(file: &[u8], secret: Secret) -> Result<Backup, String> {
+    if file.len() < HEADER_LEN + NONCE_LEN || &file[..4] != MAGIC {
+        return Err("Not a Bioguard backup".to_string());
+    }
+    let version = u16::from_le_bytes([file[4], file[5]]);
+    if version == 0 || version > VERSION {
+        return Err(format!("Backup version {} was made by a newer Bioguard, update it to restore this file", version));
+    }
+    let protection = file[6];
+    let salt = &file[7..7 + SALT_LEN];
+    let (memory_kib, passes, lanes) = (u32_at(file, 7 + SALT_LEN), u32_at(file, 11 + SALT_LEN), u32_at(file, 15 + SALT_LEN));
+    let key = match (protection, secret) {
+        (PROTECTION_PASSWORD, Secret::Password(password)) => {
+            if memory_kib > MAX_MEMORY_KIB || passes > MAX_PASSES || lanes > MAX_LANES {
+                return Err("The backup asks for too much memory or time to derive its key".to_string());
+            }
+            recovery::stretch(password, salt, memory_kib, passes, lanes).ok_or("The key derivation settings of the backup are invalid")?
+        }
+        (PROTECTION_RECOVERY_KEY, Secret::RecoveryKey(recovery_key)) => recovery_file_key(recovery_key, salt).ok_or("Not a valid recovery key")?,
+        (PROTECTION_PASSWORD, _) => return Err("This backup is protected by a password".to_string()),
+        (PROTECTION_RECOVERY_KEY, _) => return Err("This backup is protected by a recovery key".to_string()),
+        (other, _) => return Err(format!("Unknown backup protection {}", other)),
+    };
+
+    let (header, rest) = file.split_at(HEADER_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(key.as_bytes().into());
+    let json = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| "Wrong password or recovery key, or the backup is damaged".to_string())?;
+    let backup: Backup = serde_json::from_slice(&json).map_err(|e| format!("Malformed backup content: {}", e))?;
+    if backup.version != version {
+        return Err(format!("The backup header says version {} but its content version {}", version, backup.version));
+    }
+    backup.check()?;
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SecureNote;
+
+    fn sample() -> Backup {
+        let credential = Credential {
+            site: "github.com".to_string(),
+            site_username: "alice".to_string(),
+            site_password: "correct horse".to_string(),
+            created_at: "2024-01-02 03:04:05".to_string(),
+            ..Credential::default()
+        };
+        let history = vec![PasswordHistory { id: 0, credential_id: 0, site_password: "old".to_string(), changed_at: "2023-01-01 00:00:00".to_string() }];
+        let items = vec![Item { id: 0, title: "Wi-Fi".to_string(), content: ItemContent::SecureNote(SecureNote { text: "hunter2".to_string() }) }];
+        Backup::new("alice", &[(credential, history)], &items, 1_700_000_000)
+    }
+
+    #[test]
+    fn recovery_key_roundtrip() {
+        let recovery_key = recovery::new_recovery_key();
+        let file = seal(&sample(), Secret::RecoveryKey(&recovery_key)).unwrap();
+        let backup = open(&file, Secret::RecoveryKey(&recovery_key.to_lowercase().replace('-', ""))).unwrap();
+        assert_eq!(backup.username, "alice");
+        assert_eq!(backup.created_at, 1_700_000_000);
+        assert_eq!(backup.credentials[0].site_password, "correct horse");
+        assert_eq!(backup.credentials[0].history[0].site_password, "old");
+        assert_eq!(backup.items[0].content, ItemContent::SecureNote(SecureNote { text: "hunter2".to_string() }));
+    }
+
+    #[test]
+    fn wrong_secret_is_refused() {
+        let file = seal(&sample(), Secret::RecoveryKey(&recovery::new_recovery_key())).unwrap();
+        let other = recovery::new_recovery_key();
+        assert!(open(&file, Secret::RecoveryKey(&other)).unwrap_err().starts_with("Wrong password or recovery key"));
+        assert_eq!(open(&file, Secret::Password("a long backup password")).unwrap_err(), "This backup is protected by a recovery key");
+    }
+
+    #[test]
+    fn flipped_header_byte_is_refused() {
+        let recovery_key = recovery::new_recovery_key();
+        let file = seal(&sample(), Secret::RecoveryKey(&recovery_key)).unwrap();
+        //The Argon2id cost is unused with a recovery key, only the associated data protects it
+        for offset in [5, 7 + SALT_LEN, HEADER_LEN - 1] {
+            let mut tampered = file.clone();
+            tampered[offset] ^= 1;
+            assert!(open(&tampered, Secret::RecoveryKey(&recovery_key)).is_err(), "offset {}", offset);
+        }
+        let mut tampered = file;
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(open(&tampered, Secret::RecoveryKey(&recovery_key)).is_err());
+    }
+
+    #[test]
+    fn excessive_cost_is_refused_before_deriving() {
+        let mut file = vec![0u8; HEADER_LEN + NONCE_LEN + 16];
+        file[..4].copy_from_slice(MAGIC);
+        file[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        file[6] = PROTECTION_PASSWORD;
+        for (memory_kib, passes, lanes) in [(MAX_MEMORY_KIB + 1, 3, 1), (64 * 1024, u32::MAX, 1), (64 * 1024, 3, MAX_LANES + 1)] {
+            for (index, value) in [memory_kib, passes, lanes].iter().enumerate() {
+                let offset = 7 + SALT_LEN + 4 * index;
+                file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            assert_eq!(open(&file, Secret::Password("a long backup password")).unwrap_err(), "The backup asks for too much memory or time to derive its key");
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//Offline check of passwords against the Have I Been Pwned dataset downloaded on disk,
+//nothing is sent over the network. Two layouts of the SHA-1 dataset are read:
+//- the single file of every hash sorted by hash ("HASH:COUNT" lines), binary searched in place
+//- a directory of range files named after the first 5 hex digits of the hash ("0A1B2.txt"),
+//  each holding the "SUFFIX:COUNT" lines of its range as the range API serves them
+use sha1::{Digest, Sha1};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const PREFIX_LENGTH: usize = 5;
+
+pub enum Dataset {
+    Sorted { reader: BufReader<File>, length: u64 },
+    Ranges(PathBuf),
+}
+
+//Uppercase hex SHA-1, as written in the dataset
+pub fn sha1_hex(password: &str) -> String {
+    Sha1::digest(password.as_bytes()).iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+//"HASH:COUNT" line, the count is 1 for lists without counts
+fn parse_line(line: &str) -> Option<(&str, u64)> {
+    let line = line.trim_end();
+    match line.split_once(':') {
+        Some((hash, count)) => Some((hash, count.trim().parse().ok()?)),
+        None if !line.is_empty() => Some((line, 1)),
+        None => None,
+    }
+}
+
+impl Dataset {
+    pub fn open(path: &Path) -> io::Result<Dataset> {
+        if path.is_dir() {
+            return Ok(Dataset::Ranges(path.to_path_buf()));
+        }
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        Ok(Dataset::Sorted { reader: BufReader::new(file), length })
+    }
+
+    //Number of times the password was seen in breaches, 0 when it is not in the dataset
+    pub fn count(&mut self, password: &str) -> io::Result<u64> {
+        let hash = sha1_hex(password);
+        match self {
+            Dataset::Sorted { reader, length } => search_sorted(reader, *length, &hash),
+            Dataset::Ranges(directory) => search_range(directory, &hash),
+        }
+    }
+}
+
+//Binary search over byte offsets, the line of the hash (if any) always starts within [low, high)
+fn search_sorted<R: BufRead + Seek>(reader: &mut R, length: u64, hash: &str) -> io::Result<u64> {
+    let mut low = 0;
+    let mut high = length;
+    let mut line = String::new();
+    while low < high {
+        let middle = low + (high - low) / 2;
+        //Start of the first line at or after the middle: the rest of the line holding the
+        //byte before it is skipped, which is only the newline when a line starts at the middle
+        let start = if middle == 0 {
+            reader.seek(SeekFrom::Start(0))?;
+            0
+        } else {
+            reader.seek(SeekFrom::Start(middle - 1))?;
+            line.clear();
+            middle - 1 + reader.read_line(&mut line)? as u64
+        };
+        if start >= high {
+            high = middle;
+            continue;
+        }
+
+        line.clear();
+        let read = reader.read_line(&mut line)? as u64;
+        let ordering = match parse_line(&line) {
+            Some((candidate, count)) => match candidate.to_ascii_uppercase().as_str().cmp(hash) {
+                Ordering::Equal => return Ok(count),
+                ordering => ordering,
+            },
+            //Blank lines only end the file
+            None => Ordering::Greater,
+        };
+        match ordering {
+            Ordering::Less => low = start + read,
+            _ => high = middle,
+        }
+    }
+    Ok(0)
+}
+
+fn search_range(directory: &Path, hash: &str) -> io::Result<u64> {
+    let (prefix, suffix) = hash.split_at(PREFIX_LENGTH);
+    let reader = BufReader::new(File::open(directory.join(format!("{}.txt", prefix)))?);
+    for line in reader.lines() {
+        let line = line?;
+        if let Some((candidate, count)) = parse_line(&line) {
+            if candidate.eq_ignore_ascii_case(suffix) {
+                return Ok(count);
+            }
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    #[test]
+    fn sorted_file_first_last_and_absent() {
+        let mut dataset = Dataset::open(&fixture("pwned_sorted.txt")).unwrap();
+        //"password" is on the first line and "hunter2" on the last one
+        assert_eq!(dataset.count("password").unwrap(), 3861493);
+        assert_eq!(dataset.count("hunter2").unwrap(), 17043);
+        assert_eq!(dataset.count("dragon").unwrap(), 1012232);
+        assert_eq!(dataset.count("correct horse battery staple").unwrap(), 0);
+        assert_eq!(dataset.count("").unwrap(), 0);
+    }
+
+    #[test]
+    fn sorted_file_with_crlf_and_no_counts() {
+        let hashes: Vec<String> = ["password", "123456", "monkey", "hunter2"].iter().map(|password| sha1_hex(password)).collect();
+        let crlf = hashes.iter().enumerate().map(|(index, hash)| format!("{}:{}\r\n", hash, index + 1)).collect::<String>();
+        for (index, hash) in hashes.iter().enumerate() {
+            assert_eq!(search_sorted(&mut Cursor::new(crlf.as_bytes()), crlf.len() as u64, hash).unwrap(), index as u64 + 1);
+        }
+        let lowercase = hashes.iter().map(|hash| format!("{}\n", hash.to_lowercase())).collect::<String>();
+        assert_eq!(search_sorted(&mut Cursor::new(lowercase.as_bytes()), lowercase.len() as u64, &hashes[3]).unwrap(), 1);
+        assert_eq!(search_sorted(&mut Cursor::new(crlf.as_bytes()), crlf.len() as u64, &sha1_hex("dragon")).unwrap(), 0);
+    }
+
+    #[test]
+    fn range_directory() {
+        let mut dataset = Dataset::open(&fixture("pwned_ranges")).unwrap();
+        assert_eq!(dataset.count("password").unwrap(), 3861493);
+        assert_eq!(search_range(&fixture("pwned_ranges"), "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FDA").unwrap(), 0);
+        //No file for the range of "hunter2"
+        assert!(dataset.count("hunter2").is_err());
+    }
+}
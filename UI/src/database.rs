@@ -1,114 +1,914 @@
-use sqlx::{sqlite::SqlitePool, Row};
+use sqlx::{sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool}, Row};
+use std::str::FromStr;
+use std::collections::HashMap;
 use std::env;
 use dotenv::dotenv;
 
-use crate::models::{User, Credential};
+use crate::models::{User, Credential, Template, Finger, VaultRecovery, PasswordHistory, Item, ItemKind, ItemContent};
+use serde::{Deserialize, Serialize};
+use crate::vault::{self, VaultKey};
+use crate::strength;
 
 pub async fn establish_connection() -> SqlitePool {
     let database_url = "sqlite://users.db";//env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    SqlitePool::connect(&database_url).await.unwrap()
+    connect(database_url).await.unwrap()
 }
 
-pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+//SQLite leaves foreign keys off by default, they are needed for the cascading deletes
+pub async fn connect(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(database_url)?.foreign_keys(true);
+    SqlitePool::connect_with(options).await
+}
+
+//Brings the schema of users.db up to date, the applied versions are recorded by sqlx
+//in the _sqlx_migrations table. Older files created without migrations are upgraded in place.
+pub async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::migrate::MigrateError> {
+    sqlx::migrate!("./migrations").run(pool).await
+}
+
+pub async fn schema_version(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM _sqlx_migrations WHERE success = 1")
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.get("version"))
+}
+
+//Creates the user with its first finger (see save_template) and returns its id. Everything
+//is written in one transaction, a failed registration leaves no user without a finger behind.
+pub async fn save_user(pool: &SqlitePool, username: &str, finger: Finger, template: Vec<u8>, keys: &[i64], helper: Vec<u8>, wrapped_key: Vec<u8>) -> Result<i64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query(
+        "
+        INSERT INTO users (username, fingerprint_image) VALUES (?, X'')
+        "
+    )
+    .bind(username)
+    .execute(&mut tx)
+    .await?;
+    let user_id = result.last_insert_rowid();
+    insert_template(&mut tx, user_id, finger, template, keys, helper, wrapped_key).await?;
+    tx.commit().await?;
+
+    Ok(user_id)
+}
+
+//Stores the template of a finger with its index keys and key release data, replacing the
+//previous template of that finger, and returns its id. Written in one transaction: a template
+//is never left that the index cannot find or that cannot release the vault key.
+pub async fn save_template(pool: &SqlitePool, user_id: i64, finger: Finger, template: Vec<u8>, keys: &[i64], helper: Vec<u8>, wrapped_key: Vec<u8>) -> Result<i64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let template_id = insert_template(&mut tx, user_id, finger, template, keys, helper, wrapped_key).await?;
+    tx.commit().await?;
+
+    Ok(template_id)
+}
+
+async fn insert_template(conn: &mut SqliteConnection, user_id: i64, finger: Finger, template: Vec<u8>, keys: &[i64], helper: Vec<u8>, wrapped_key: Vec<u8>) -> Result<i64, sqlx::Error> {
+    delete_finger(conn, user_id, finger).await?;
+    let result = sqlx::query(
+        "
+        INSERT INTO templates (user_id, finger, template) VALUES (?, ?, ?)
+        "
+    )
+    .bind(user_id)
+    .bind(finger.as_str())
+    .bind(template)
+    .execute(&mut *conn)
+    .await?;
+    let template_id = result.last_insert_rowid();
+    insert_template_keys(conn, template_id, keys).await?;
+    sqlx::query("INSERT INTO key_release (template_id, helper, wrapped_key) VALUES (?, ?, ?)")
+    .bind(template_id)
+    .bind(helper)
+    .bind(wrapped_key)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(template_id)
+}
+
+pub async fn get_templates(pool: &SqlitePool, user_id: i64) -> Result<Vec<Template>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, user_id, finger, template FROM templates WHERE user_id = ? ORDER BY id")
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let templates = rows.into_iter().map(|row| Template {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        finger: Finger::from_db(row.get("finger")),
+        template: row.get("template"),
+    }).collect();
+
+    Ok(templates)
+}
+
+//Every enrolled template with the username it belongs to, used for 1:N identification
+pub async fn get_all_templates(pool: &SqlitePool) -> Result<Vec<(String, Template)>, sqlx::Error> {
+    let rows = sqlx::query(
+        "
+        SELECT templates.id, templates.user_id, templates.finger, templates.template, users.username
+        FROM templates JOIN users ON users.id = templates.user_id
+        "
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let templates = rows.into_iter().map(|row| (
+        row.get("username"),
+        Template {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            finger: Finger::from_db(row.get("finger")),
+            template: row.get("template"),
+        },
+    )).collect();
+
+    Ok(templates)
+}
+
+pub async fn update_template(pool: &SqlitePool, template_id: i64, template: Vec<u8>) -> Result<(), sqlx::Error> {
     sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            username TEXT NOT NULL UNIQUE,
-            fingerprint_image BLOB NOT NULL
-        )
-        "#
+        "
+        UPDATE templates SET template = ? WHERE id = ?
+        "
     )
+    .bind(template)
+    .bind(template_id)
     .execute(pool)
     .await?;
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS credentials (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            username TEXT NOT NULL,
-            site TEXT NOT NULL,
-            site_username TEXT NOT NULL,
-            site_password TEXT NOT NULL
-        )
-        "#
+
+    Ok(())
+}
+
+//Templates stored before the index existed
+pub async fn get_unindexed_templates(pool: &SqlitePool) -> Result<Vec<Template>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, user_id, finger, template FROM templates WHERE id NOT IN (SELECT template_id FROM template_index)")
+    .fetch_all(pool)
+    .await?;
+
+    let templates = rows.into_iter().map(|row| Template {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        finger: Finger::from_db(row.get("finger")),
+        template: row.get("template"),
+    }).collect();
+
+    Ok(templates)
+}
+
+pub async fn get_template_with_user(pool: &SqlitePool, template_id: i64) -> Result<Option<(String, Template)>, sqlx::Error> {
+    match sqlx::query(
+        "
+        SELECT templates.id, templates.user_id, templates.finger, templates.template, users.username
+        FROM templates JOIN users ON users.id = templates.user_id
+        WHERE templates.id = ?
+        "
     )
+    .bind(template_id)
+    .fetch_optional(pool)
+    .await? {
+        Some(row) => Ok(Some((
+            row.get("username"),
+            Template {
+                id: row.get("id"),
+                user_id: row.get("user_id"),
+                finger: Finger::from_db(row.get("finger")),
+                template: row.get("template"),
+            },
+        ))),
+        None => Ok(None),
+    }
+}
+
+pub async fn save_template_keys(pool: &SqlitePool, template_id: i64, keys: &[i64]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    insert_template_keys(&mut tx, template_id, keys).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn insert_template_keys(conn: &mut SqliteConnection, template_id: i64, keys: &[i64]) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM template_index WHERE template_id = ?")
+    .bind(template_id)
+    .execute(&mut *conn)
+    .await?;
+    for key in keys {
+        sqlx::query("INSERT INTO template_index (template_id, key) VALUES (?, ?)")
+        .bind(template_id)
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn has_template_keys(pool: &SqlitePool, template_id: i64) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("SELECT EXISTS (SELECT 1 FROM template_index WHERE template_id = ?) AS indexed")
+    .bind(template_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.get("indexed"))
+}
+
+pub async fn clear_template_index(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM template_index")
     .execute(pool)
     .await?;
 
     Ok(())
 }
 
-pub async fn save_user(pool: &SqlitePool, username: &str, fingerprint_image: Vec<u8>) -> Result<(), sqlx::Error> {
+//Number of keys each template shares with the probe, with its total number of keys
+pub async fn get_template_votes(pool: &SqlitePool, keys: &[i64]) -> Result<HashMap<i64, (usize, usize)>, sqlx::Error> {
+    let mut votes = HashMap::new();
+
+    //Stay under the SQLite limit of bound parameters
+    for chunk in keys.chunks(500) {
+        let placeholders = vec!["?"; chunk.len()].join(", ");
+        let sql = format!(
+            "
+            SELECT template_id, COUNT(*) AS votes,
+                (SELECT COUNT(*) FROM template_index AS own WHERE own.template_id = template_index.template_id) AS total
+            FROM template_index WHERE key IN ({}) GROUP BY template_id
+            ",
+            placeholders
+        );
+        let mut query = sqlx::query(&sql);
+        for key in chunk {
+            query = query.bind(key);
+        }
+        for row in query.fetch_all(pool).await? {
+            let count: i64 = row.get("votes");
+            let total: i64 = row.get("total");
+            let entry = votes.entry(row.get("template_id")).or_insert((0, total as usize));
+            entry.0 += count as usize;
+        }
+    }
+
+    Ok(votes)
+}
+
+pub async fn delete_templates(pool: &SqlitePool, user_id: i64, finger: Finger) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    delete_finger(&mut tx, user_id, finger).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn delete_finger(conn: &mut SqliteConnection, user_id: i64, finger: Finger) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        DELETE FROM template_index WHERE template_id IN (SELECT id FROM templates WHERE user_id = ? AND finger = ?)
+        "
+    )
+    .bind(user_id)
+    .bind(finger.as_str())
+    .execute(&mut *conn)
+    .await?;
     sqlx::query(
         "
-        INSERT INTO users (username, fingerprint_image) VALUES (?, ?)
+        DELETE FROM key_release WHERE template_id IN (SELECT id FROM templates WHERE user_id = ? AND finger = ?)
+        "
+    )
+    .bind(user_id)
+    .bind(finger.as_str())
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "
+        DELETE FROM templates WHERE user_id = ? AND finger = ?
+        "
+    )
+    .bind(user_id)
+    .bind(finger.as_str())
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+//The site and tags stay in clear to find the rows, the account fields, url and notes are encrypted with the vault key
+pub async fn save_credentials(pool: &SqlitePool, key: &VaultKey, username: &str, credential: &Credential) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        INSERT INTO credentials (user_id, site, site_username, site_password, url, notes, tags, favourite, otp, created_at, modified_at, password_changed_at)
+        VALUES ((SELECT id FROM users WHERE username = ?), ?, ?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now'), strftime('%s', 'now'), strftime('%s', 'now'))
         "
     )
     .bind(username)
-    .bind(fingerprint_image)
+    .bind(&credential.site)
+    .bind(vault::encrypt_str(key, "site_username", &credential.site_username))
+    .bind(vault::encrypt_str(key, "site_password", &credential.site_password))
+    .bind(vault::encrypt_str(key, "url", &credential.url))
+    .bind(vault::encrypt_str(key, "notes", &credential.notes))
+    .bind(&credential.tags)
+    .bind(credential.favourite)
+    .bind(encrypt_optional(key, "otp", &credential.otp))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(())
+}
+
+//Fuzzy extractor helper data and vault key wrapped with the key it releases
+pub async fn save_key_release(pool: &SqlitePool, template_id: i64, helper: Vec<u8>, wrapped_key: Vec<u8>) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT OR REPLACE INTO key_release (template_id, helper, wrapped_key) VALUES (?, ?, ?)")
+    .bind(template_id)
+    .bind(helper)
+    .bind(wrapped_key)
     .execute(pool)
     .await?;
 
     Ok(())
 }
 
-pub async fn save_credentials(pool: &SqlitePool, username: &str, site: &str, site_username: &str, site_password: &str) -> Result<(), sqlx::Error> {
+pub async fn get_key_release(pool: &SqlitePool, template_id: i64) -> Result<Option<(Vec<u8>, Vec<u8>)>, sqlx::Error> {
+    let row = sqlx::query("SELECT helper, wrapped_key FROM key_release WHERE template_id = ?")
+    .bind(template_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| (row.get("helper"), row.get("wrapped_key"))))
+}
+
+//Number of templates of the user, and how many of them can release the vault key
+pub async fn count_key_releases(pool: &SqlitePool, user_id: i64) -> Result<(i64, i64), sqlx::Error> {
+    let row = sqlx::query(
+        "
+        SELECT COUNT(*) AS templates, COUNT(key_release.template_id) AS releases
+        FROM templates LEFT JOIN key_release ON key_release.template_id = templates.id
+        WHERE templates.user_id = ?
+        "
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok((row.get("templates"), row.get("releases")))
+}
+
+pub async fn get_vault_recovery(pool: &SqlitePool, user_id: i64) -> Result<VaultRecovery, sqlx::Error> {
+    let row = sqlx::query("SELECT password_salt, password_wrapped_key, recovery_wrapped_key FROM vault_recovery WHERE user_id = ?")
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(match row {
+        Some(row) => VaultRecovery {
+            password_salt: row.get("password_salt"),
+            password_wrapped_key: row.get("password_wrapped_key"),
+            recovery_wrapped_key: row.get("recovery_wrapped_key"),
+        },
+        None => VaultRecovery::default(),
+    })
+}
+
+//Sets or changes the master password
+pub async fn save_master_password(pool: &SqlitePool, user_id: i64, salt: Vec<u8>, wrapped_key: Vec<u8>) -> Result<(), sqlx::Error> {
     sqlx::query(
         "
-        INSERT INTO credentials (username, site, site_username, site_password) VALUES (?, ?, ?, ?)
+        INSERT INTO vault_recovery (user_id, password_salt, password_wrapped_key) VALUES (?, ?, ?)
+        ON CONFLICT (user_id) DO UPDATE SET password_salt = excluded.password_salt, password_wrapped_key = excluded.password_wrapped_key
+        "
+    )
+    .bind(user_id)
+    .bind(salt)
+    .bind(wrapped_key)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+//Stores a new recovery key, the previous one stops working
+pub async fn save_recovery_key(pool: &SqlitePool, user_id: i64, wrapped_key: Vec<u8>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        INSERT INTO vault_recovery (user_id, recovery_wrapped_key) VALUES (?, ?)
+        ON CONFLICT (user_id) DO UPDATE SET recovery_wrapped_key = excluded.recovery_wrapped_key
+        "
+    )
+    .bind(user_id)
+    .bind(wrapped_key)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+//Encrypts the credentials saved in plaintext by older versions
+pub async fn encrypt_legacy_credentials(pool: &SqlitePool, key: &VaultKey, username: &str) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query(
+        "
+        SELECT id, site_username, site_password FROM credentials
+        WHERE user_id = (SELECT id FROM users WHERE username = ?) AND (typeof(site_username) = 'text' OR typeof(site_password) = 'text')
         "
     )
     .bind(username)
-    .bind(site)
-    .bind(site_username)
-    .bind(site_password)
     .fetch_all(pool)
     .await?;
 
+    for row in rows {
+        let id: i64 = row.get("id");
+        let site_username: String = row.get("site_username");
+        let site_password: String = row.get("site_password");
+        sqlx::query("UPDATE credentials SET site_username = ?, site_password = ? WHERE id = ?")
+        .bind(vault::encrypt_str(key, "site_username", &site_username))
+        .bind(vault::encrypt_str(key, "site_password", &site_password))
+        .bind(id)
+        .execute(pool)
+        .await?;
+    }
+
     Ok(())
 }
 
 pub async fn get_user(pool: &SqlitePool, username: &str) -> Result<Option<User>, sqlx::Error> {
-    match sqlx::query("SELECT id, username, fingerprint_image FROM users WHERE username = ?")
+    match sqlx::query("SELECT id, username FROM users WHERE username = ?")
         .bind(username)
         .fetch_optional(pool)
         .await? {
         Some(row) => Ok(Some(User {
             id: row.get("id"),
             username: row.get("username"),
-            fingerprint_image: row.get("fingerprint_image"),
         })),
         None => Ok(None),
     }
 }
 
-pub async fn get_credentials(pool: &SqlitePool, username: &str) -> Result<Vec<Credential>, sqlx::Error> {
-    let rows = sqlx::query("SELECT username, site, site_username, site_password FROM credentials WHERE username = ?")
+//The account fields are only decrypted in memory, rows that fail to decrypt are skipped
+pub async fn get_credentials(pool: &SqlitePool, key: &VaultKey, username: &str) -> Result<Vec<Credential>, sqlx::Error> {
+    let rows = sqlx::query(
+        "
+        SELECT credentials.id, users.username, credentials.site, credentials.site_username, credentials.site_password,
+            credentials.url, credentials.notes, credentials.tags, credentials.favourite, credentials.otp,
+            COALESCE(datetime(credentials.created_at, 'unixepoch'), '') AS created_at,
+            COALESCE(datetime(credentials.modified_at, 'unixepoch'), '') AS modified_at,
+            COALESCE(datetime(credentials.last_used_at, 'unixepoch'), '') AS last_used_at,
+            COALESCE(datetime(credentials.password_changed_at, 'unixepoch'), '') AS password_changed_at
+        FROM credentials JOIN users ON users.id = credentials.user_id
+        WHERE users.username = ? AND typeof(credentials.site_username) = 'blob' AND typeof(credentials.site_password) = 'blob'
+        ORDER BY credentials.favourite DESC, credentials.site
+        "
+    )
     .bind(username)
     .fetch_all(pool)
     .await?;
 
-    let credentials = rows.into_iter().map(|row| Credential {
-        id: 0,
-        username: row.get("username"),
-        site: row.get("site"),
-        site_username: row.get("site_username"),
-        site_password: row.get("site_password"),
+    let credentials = rows.into_iter().filter_map(|row| {
+        let site: String = row.get("site");
+        let site_username = vault::decrypt_str(key, "site_username", &row.get::<Vec<u8>, _>("site_username"));
+        let site_password = vault::decrypt_str(key, "site_password", &row.get::<Vec<u8>, _>("site_password"));
+        let url = decrypt_optional(key, "url", row.get("url"));
+        let notes = decrypt_optional(key, "notes", row.get("notes"));
+        let otp = decrypt_optional(key, "otp", row.get("otp"));
+        match (site_username, site_password, url, notes, otp) {
+            (Some(site_username), Some(site_password), Some(url), Some(notes), Some(otp)) => Some(Credential {
+                strength: strength::estimate(&site_password).score,
+                id: row.get("id"),
+                username: row.get("username"),
+                site,
+                site_username,
+                site_password,
+                url,
+                notes,
+                tags: row.get("tags"),
+                favourite: row.get("favourite"),
+                otp,
+                otp_code: "".to_string(),
+                breaches: 0,
+                created_at: row.get("created_at"),
+                modified_at: row.get("modified_at"),
+                last_used_at: row.get("last_used_at"),
+                password_changed_at: row.get("password_changed_at"),
+            }),
+            _ => {
+                eprintln!("Failed to decrypt the credential for {}", site);
+                None
+            }
+        }
     }).collect();
 
     Ok(credentials)
 }
 
-pub async fn delete_credentials(pool: &SqlitePool, username: &str, site: &str) -> Result<(), sqlx::Error> {
+//The owner is checked as well so a stale id can never touch the row of another user, a row that
+//is gone (or belongs to someone else) is reported as RowNotFound and nothing is written.
+//A changed password is moved to credential_history, only the last `retention` entries are kept.
+pub async fn update_credentials(pool: &SqlitePool, key: &VaultKey, username: &str, credential: &Credential, retention: usize) -> Result<(), sqlx::Error> {
+    let id = credential.id;
+    let mut tx = pool.begin().await?;
+
+    let current = sqlx::query(
+        "
+        SELECT site_password FROM credentials
+        WHERE id = ? AND user_id = (SELECT id FROM users WHERE username = ?)
+        "
+    )
+    .bind(id)
+    .bind(username)
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let current = match current {
+        Some(row) => row.get::<Vec<u8>, _>("site_password"),
+        None => return Err(sqlx::Error::RowNotFound),
+    };
+
+    let changed = vault::decrypt_str(key, "site_password", &current).as_deref() != Some(credential.site_password.as_str());
+    if changed && retention > 0 {
+        sqlx::query("INSERT INTO credential_history (credential_id, site_password) VALUES (?, ?)")
+        .bind(id)
+        .bind(current)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query(
+            "
+            DELETE FROM credential_history WHERE credential_id = ? AND id NOT IN (
+                SELECT id FROM credential_history WHERE credential_id = ? ORDER BY id DESC LIMIT ?
+            )
+            "
+        )
+        .bind(id)
+        .bind(id)
+        .bind(retention as i64)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let result = sqlx::query(
+        "
+        UPDATE credentials SET site = ?, site_username = ?, site_password = ?, url = ?, notes = ?, tags = ?, favourite = ?,
+            otp = ?, modified_at = strftime('%s', 'now'),
+            password_changed_at = CASE WHEN ? THEN strftime('%s', 'now') ELSE password_changed_at END
+        WHERE id = ? AND user_id = (SELECT id FROM users WHERE username = ?)
+        "
+    )
+    .bind(&credential.site)
+    .bind(vault::encrypt_str(key, "site_username", &credential.site_username))
+    .bind(vault::encrypt_str(key, "site_password", &credential.site_password))
+    .bind(vault::encrypt_str(key, "url", &credential.url))
+    .bind(vault::encrypt_str(key, "notes", &credential.notes))
+    .bind(&credential.tags)
+    .bind(credential.favourite)
+    .bind(encrypt_optional(key, "otp", &credential.otp))
+    .bind(changed)
+    .bind(id)
+    .bind(username)
+    .execute(&mut tx)
+    .await?;
+    //The transaction is rolled back when dropped, the history entry goes with it
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+//Stores the HOTP secret with its counter moved past the code just used
+pub async fn update_otp(pool: &SqlitePool, key: &VaultKey, username: &str, id: i32, otp: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        UPDATE credentials SET otp = ?
+        WHERE id = ? AND user_id = (SELECT id FROM users WHERE username = ?)
+        "
+    )
+    .bind(encrypt_optional(key, "otp", otp))
+    .bind(id)
+    .bind(username)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+//Called when the password of the credential is copied
+pub async fn touch_credentials(pool: &SqlitePool, username: &str, id: i32) -> Result<(), sqlx::Error> {
     sqlx::query(
         "
-        DELETE FROM credentials WHERE username = ? AND site = ?
+        UPDATE credentials SET last_used_at = strftime('%s', 'now')
+        WHERE id = ? AND user_id = (SELECT id FROM users WHERE username = ?)
         "
     )
+    .bind(id)
+    .bind(username)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+//Newest first, entries that fail to decrypt are skipped like in get_credentials
+pub async fn get_password_history(pool: &SqlitePool, key: &VaultKey, username: &str, credential_id: i32) -> Result<Vec<PasswordHistory>, sqlx::Error> {
+    let rows = sqlx::query(
+        "
+        SELECT credential_history.id, credential_history.credential_id, credential_history.site_password,
+            datetime(credential_history.changed_at, 'unixepoch') AS changed_at
+        FROM credential_history
+        JOIN credentials ON credentials.id = credential_history.credential_id
+        WHERE credential_history.credential_id = ? AND credentials.user_id = (SELECT id FROM users WHERE username = ?)
+        ORDER BY credential_history.id DESC
+        "
+    )
+    .bind(credential_id)
+    .bind(username)
+    .fetch_all(pool)
+    .await?;
+
+    let history = rows.into_iter().filter_map(|row| {
+        let site_password = vault::decrypt_str(key, "site_password", &row.get::<Vec<u8>, _>("site_password"))?;
+        Some(PasswordHistory {
+            id: row.get("id"),
+            credential_id: row.get("credential_id"),
+            site_password,
+            changed_at: row.get("changed_at"),
+        })
+    }).collect();
+
+    Ok(history)
+}
+
+//NULL columns are empty values, rows saved before the column existed
+fn decrypt_optional(key: &VaultKey, field: &str, blob: Option<Vec<u8>>) -> Option<String> {
+    match blob {
+        Some(blob) => vault::decrypt_str(key, field, &blob),
+        None => Some(String::new()),
+    }
+}
+
+//Empty values are stored as NULL
+fn encrypt_optional(key: &VaultKey, field: &str, value: &str) -> Option<Vec<u8>> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(vault::encrypt_str(key, field, value))
+    }
+}
+
+//Only the given row, a user can have several accounts on the same site
+pub async fn delete_credentials(pool: &SqlitePool, username: &str, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        DELETE FROM credentials WHERE id = ? AND user_id = (SELECT id FROM users WHERE username = ?)
+        "
+    )
+    .bind(id)
     .bind(username)
-    .bind(site)
     .fetch_all(pool)
     .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+//Encrypted part of an item, the kind is part of the associated data so a payload cannot be moved to another kind
+#[derive(Serialize, Deserialize)]
+struct ItemPayload {
+    title: String,
+    content: ItemContent,
+}
+
+fn item_field(kind: ItemKind) -> String {
+    format!("item:{}", kind.as_str())
+}
+
+fn encrypt_item(key: &VaultKey, item: &Item) -> Vec<u8> {
+    let payload = ItemPayload { title: item.title.clone(), content: item.content.clone() };
+    let json = serde_json::to_string(&payload).expect("Failed to serialize the item");
+    vault::encrypt_str(key, &item_field(item.content.kind()), &json)
+}
+
+pub async fn save_item(pool: &SqlitePool, key: &VaultKey, username: &str, item: &Item) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query(
+        "
+        INSERT INTO items (user_id, kind, payload) VALUES ((SELECT id FROM users WHERE username = ?), ?, ?)
+        "
+    )
+    .bind(username)
+    .bind(item.content.kind().as_str())
+    .bind(encrypt_item(key, item))
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn update_item(pool: &SqlitePool, key: &VaultKey, username: &str, item: &Item) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        UPDATE items SET kind = ?, payload = ?, modified_at = strftime('%s', 'now')
+        WHERE id = ? AND user_id = (SELECT id FROM users WHERE username = ?)
+        "
+    )
+    .bind(item.content.kind().as_str())
+    .bind(encrypt_item(key, item))
+    .bind(item.id)
+    .bind(username)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+//Grouped by kind, items that fail to decrypt or to parse are skipped
+pub async fn get_items(pool: &SqlitePool, key: &VaultKey, username: &str) -> Result<Vec<Item>, sqlx::Error> {
+    let rows = sqlx::query(
+        "
+        SELECT items.id, items.kind, items.payload FROM items
+        JOIN users ON users.id = items.user_id
+        WHERE users.username = ?
+        ORDER BY items.kind, items.id
+        "
+    )
+    .bind(username)
+    .fetch_all(pool)
+    .await?;
+
+    let items = rows.into_iter().filter_map(|row| {
+        let id: i64 = row.get("id");
+        let kind = ItemKind::from_db(&row.get::<String, _>("kind"))?;
+        let json = vault::decrypt_str(key, &item_field(kind), &row.get::<Vec<u8>, _>("payload"));
+        match json.and_then(|json| serde_json::from_str::<ItemPayload>(&json).ok()) {
+            Some(payload) if payload.content.kind() == kind => Some(Item {
+                id,
+                title: payload.title,
+                content: payload.content,
+            }),
+            _ => {
+                eprintln!("Failed to decrypt the item {}", id);
+                None
+            }
+        }
+    }).collect();
+
+    Ok(items)
+}
+
+pub async fn delete_item(pool: &SqlitePool, username: &str, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        DELETE FROM items WHERE id = ? AND user_id = (SELECT id FROM users WHERE username = ?)
+        "
+    )
+    .bind(id)
+    .bind(username)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+//Writes the credentials, their previous passwords and the items of a restored backup in one
+//transaction: a failure leaves the vault as it was. With replace the credentials (and their
+//history) and the items of the user are deleted first. The dates of the backup are kept,
+//the missing ones are set to now.
+pub async fn restore_vault(pool: &SqlitePool, key: &VaultKey, username: &str, credentials: &[(Credential, Vec<PasswordHistory>)], items: &[Item], replace: bool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    if replace {
+        sqlx::query("DELETE FROM credentials WHERE user_id = (SELECT id FROM users WHERE username = ?)")
+        .bind(username)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("DELETE FROM items WHERE user_id = (SELECT id FROM users WHERE username = ?)")
+        .bind(username)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    for (credential, history) in credentials {
+        let result = sqlx::query(
+            "
+            INSERT INTO credentials (user_id, site, site_username, site_password, url, notes, tags, favourite, otp,
+                created_at, modified_at, last_used_at, password_changed_at)
+            VALUES ((SELECT id FROM users WHERE username = ?), ?, ?, ?, ?, ?, ?, ?, ?,
+                COALESCE(strftime('%s', NULLIF(?, '')), strftime('%s', 'now')),
+                COALESCE(strftime('%s', NULLIF(?, '')), strftime('%s', 'now')),
+                strftime('%s', NULLIF(?, '')),
+                COALESCE(strftime('%s', NULLIF(?, '')), strftime('%s', 'now')))
+            "
+        )
+        .bind(username)
+        .bind(&credential.site)
+        .bind(vault::encrypt_str(key, "site_username", &credential.site_username))
+        .bind(vault::encrypt_str(key, "site_password", &credential.site_password))
+        .bind(vault::encrypt_str(key, "url", &credential.url))
+        .bind(vault::encrypt_str(key, "notes", &credential.notes))
+        .bind(&credential.tags)
+        .bind(credential.favourite)
+        .bind(encrypt_optional(key, "otp", &credential.otp))
+        .bind(&credential.created_at)
+        .bind(&credential.modified_at)
+        .bind(&credential.last_used_at)
+        .bind(&credential.password_changed_at)
+        .execute(&mut tx)
+        .await?;
+        let credential_id = result.last_insert_rowid();
+
+        //Oldest first so the ids keep the order get_password_history sorts on
+        for entry in history.iter().rev() {
+            sqlx::query("INSERT INTO credential_history (credential_id, site_password, changed_at) VALUES (?, ?, strftime('%s', ?))")
+            .bind(credential_id)
+            .bind(vault::encrypt_str(key, "site_password", &entry.site_password))
+            .bind(&entry.changed_at)
+            .execute(&mut tx)
+            .await?;
+        }
+    }
+
+    for item in items {
+        sqlx::query("INSERT INTO items (user_id, kind, payload) VALUES ((SELECT id FROM users WHERE username = ?), ?, ?)")
+        .bind(username)
+        .bind(item.content.kind().as_str())
+        .bind(encrypt_item(key, item))
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    //One connection, every connection to :memory: opens its own empty database
+    async fn memory_pool() -> SqlitePool {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap().foreign_keys(true);
+        let pool = SqlitePoolOptions::new().max_connections(1).connect_with(options).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+        pool
+    }
+
+    async fn register(pool: &SqlitePool, username: &str) -> Result<i64, sqlx::Error> {
+        save_user(pool, username, Finger::RightIndex, vec![1], &[10, 11], vec![2], vec![3]).await
+    }
+
+    #[tokio::test]
+    async fn failed_registration_leaves_no_user() {
+        let pool = memory_pool().await;
+        let user_id = register(&pool, "alice").await.unwrap();
+        assert_eq!(count_key_releases(&pool, user_id).await.unwrap(), (1, 1));
+        assert_eq!(get_template_votes(&pool, &[10, 12]).await.unwrap(), HashMap::from([(1, (1, 2))]));
+        assert!(register(&pool, "alice").await.is_err());
+
+        sqlx::query("DROP TABLE key_release").execute(&pool).await.unwrap();
+        assert!(register(&pool, "bob").await.is_err());
+        assert!(get_user(&pool, "bob").await.unwrap().is_none());
+        assert_eq!(get_all_templates(&pool).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_enrolment_keeps_the_previous_template() {
+        let pool = memory_pool().await;
+        let user_id = register(&pool, "alice").await.unwrap();
+        let replaced = save_template(&pool, user_id, Finger::RightIndex, vec![4], &[20], vec![5], vec![6]).await.unwrap();
+        let templates = get_templates(&pool, user_id).await.unwrap();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0].template, vec![4]);
+        assert_eq!(get_template_votes(&pool, &[10, 11, 20]).await.unwrap(), HashMap::from([(replaced, (1, 1))]));
+
+        sqlx::query("DROP TABLE key_release").execute(&pool).await.unwrap();
+        assert!(save_template(&pool, user_id, Finger::RightIndex, vec![7], &[30], vec![8], vec![9]).await.is_err());
+        let templates = get_templates(&pool, user_id).await.unwrap();
+        assert_eq!((templates.len(), templates[0].id), (1, replaced));
+        assert_eq!(get_template_votes(&pool, &[20, 30]).await.unwrap(), HashMap::from([(replaced, (1, 1))]));
+    }
+
+    #[tokio::test]
+    async fn update_of_a_missing_credential_is_an_error() {
+        let pool = memory_pool().await;
+        let key = VaultKey::generate();
+        register(&pool, "alice").await.unwrap();
+        register(&pool, "bob").await.unwrap();
+        let credential = Credential {
+            site: "github.com".to_string(),
+            site_username: "alice".to_string(),
+            site_password: "correct horse".to_string(),
+            ..Credential::default()
+        };
+        save_credentials(&pool, &key, "alice", &credential).await.unwrap();
+        let mut saved = get_credentials(&pool, &key, "alice").await.unwrap().remove(0);
+
+        saved.site_password = "battery staple".to_string();
+        assert!(matches!(update_credentials(&pool, &key, "bob", &saved, 5).await, Err(sqlx::Error::RowNotFound)));
+        assert_eq!(get_credentials(&pool, &key, "alice").await.unwrap()[0].site_password, "correct horse");
+
+        update_credentials(&pool, &key, "alice", &saved, 5).await.unwrap();
+        assert_eq!(get_credentials(&pool, &key, "alice").await.unwrap()[0].site_password, "battery staple");
+        assert_eq!(get_password_history(&pool, &key, "alice", saved.id).await.unwrap().len(), 1);
+
+        delete_credentials(&pool, "alice", saved.id).await.unwrap();
+        assert!(matches!(update_credentials(&pool, &key, "alice", &saved, 5).await, Err(sqlx::Error::RowNotFound)));
+    }
+}
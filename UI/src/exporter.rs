@@ -0,0 +1,407 @@
+//Plaintext exports in the layouts other password managers import, the exit path out of
+//Bioguard. The files are not encrypted: the UI only writes them after a fresh fingerprint
+//verification and an explicit confirmation. What a layout has no place for is listed in
+//Export::lost rather than dropped without a word.
+use crate::models::{Credential, ExportLayout, Item, ItemContent};
+use serde_json::{json, Value};
+
+pub struct Export {
+    pub data: Vec<u8>,
+    pub credentials: usize,
+    pub items: usize,
+    //Parts of the vault the layout could not hold, e.g. "3 items"
+    pub lost: Vec<String>,
+}
+
+impl Export {
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} credentials and {} items", self.credentials, self.items);
+        if !self.lost.is_empty() {
+            summary.push_str(&format!(", not included in this layout: {}", self.lost.join(", ")));
+        }
+        summary
+    }
+}
+
+pub fn export(layout: ExportLayout, credentials: &[Credential], items: &[Item]) -> Result<Export, String> {
+    match layout {
+        ExportLayout::BitwardenJson => bitwarden_json(credentials, items),
+        ExportLayout::BitwardenCsv => bitwarden_csv(credentials, items),
+        ExportLayout::ChromeCsv => chrome_csv(credentials, items),
+    }
+}
+
+//Bitwarden has one folder per entry, the first tag is the folder and the full list is kept
+//in a custom field when there are several
+fn folder(credential: &Credential) -> (String, Option<String>) {
+    let tags: Vec<&str> = credential.tags.split(',').map(|tag| tag.trim()).filter(|tag| !tag.is_empty()).collect();
+    match tags.as_slice() {
+        [] => (String::new(), None),
+        [tag] => (tag.to_string(), None),
+        [first, ..] => (first.to_string(), Some(tags.join(", "))),
+    }
+}
+
+fn empty_as_null(value: &str) -> Value {
+    if value.is_empty() {
+        Value::Null
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+//Bitwarden ids only link the items to their folders, they are renumbered on import
+fn bitwarden_id(index: usize) -> String {
+    format!("00000000-0000-4000-8000-{:012x}", index)
+}
+
+//"MM/YY" to Bitwarden month "M" and year "YYYY"
+fn card_expiry(expiry: &str) -> (Value, Value) {
+    match expiry.split_once('/') {
+        Some((month, year)) => {
+            let month = month.trim().trim_start_matches('0');
+            let year = year.trim();
+            let year = if year.len() == 2 { format!("20{}", year) } else { year.to_string() };
+            (empty_as_null(month), empty_as_null(&year))
+        }
+        None => (Value::Null, Value::Null),
+    }
+}
+
+fn bitwarden_field(name: &str, value: &str, hidden: bool) -> Value {
+    json!({ "name": name, "value": value, "type": if hidden { 1 } else { 0 }, "linkedId": null })
+}
+
+fn bitwarden_item(id: usize, kind: u8, name: &str, notes: &str, favourite: bool, folder_id: Value, fields: Vec<Value>) -> Value {
+    json!({
+        "id": bitwarden_id(id),
+        "organizationId": null,
+        "folderId": folder_id,
+        "type": kind,
+        "reprompt": 0,
+        "name": name,
+        "notes": empty_as_null(notes),
+        "favorite": favourite,
+        "fields": fields,
+        "collectionIds": null,
+    })
+}
+
+fn bitwarden_json(credentials: &[Credential], items: &[Item]) -> Result<Export, String> {
+    let mut folders: Vec<String> = Vec::new();
+    let mut entries = Vec::new();
+    for credential in credentials {
+        let (name, tags) = folder(credential);
+        let folder_id = if name.is_empty() {
+            Value::Null
+        } else {
+            let index = match folders.iter().position(|folder| *folder == name) {
+                Some(index) => index,
+                None => {
+                    folders.push(name);
+                    folders.len() - 1
+                }
+            };
+            Value::String(bitwarden_id(index + 1))
+        };
+        let fields = tags.map(|tags| vec![bitwarden_field("Tags", &tags, false)]).unwrap_or_default();
+        let mut item = bitwarden_item(entries.len() + 1, 1, &credential.site, &credential.notes, credential.favourite, folder_id, fields);
+        let uris: Vec<Value> = if credential.url.is_empty() { Vec::new() } else { vec![json!({ "match": null, "uri": credential.url })] };
+        item["login"] = json!({
+            "uris": uris,
+            "username": empty_as_null(&credential.site_username),
+            "password": credential.site_password,
+            "totp": empty_as_null(&credential.otp),
+        });
+        entries.push(item);
+    }
+
+    for entry in items {
+        let id = entries.len() + 1;
+        let item = match &entry.content {
+            ItemContent::SecureNote(note) => {
+                let mut item = bitwarden_item(id, 2, &entry.title, &note.text, false, Value::Null, Vec::new());
+                item["secureNote"] = json!({ "type": 0 });
+                item
+            }
+            ItemContent::Card(card) => {
+                let (month, year) = card_expiry(&card.expiry);
+                let mut item = bitwarden_item(id, 3, &entry.title, "", false, Value::Null, Vec::new());
+                item["card"] = json!({
+                    "cardholderName": empty_as_null(&card.cardholder),
+                    "brand": null,
+                    "number": empty_as_null(&card.number),
+                    "expMonth": month,
+                    "expYear": year,
+                    "code": empty_as_null(&card.security_code),
+                });
+                item
+            }
+            ItemContent::Identity(identity) => {
+                let (first, last) = identity.full_name.trim().split_once(' ').unwrap_or((identity.full_name.trim(), ""));
+                let mut item = bitwarden_item(id, 4, &entry.title, "", false, Value::Null, Vec::new());
+                item["identity"] = json!({
+                    "firstName": empty_as_null(first),
+                    "lastName": empty_as_null(last.trim()),
+                    "email": empty_as_null(&identity.email),
+                    "phone": empty_as_null(&identity.phone),
+                    "address1": empty_as_null(&identity.address),
+                });
+                item
+            }
+            //No dedicated type in every Bitwarden version: a secure note with hidden fields for the secrets
+            ItemContent::SshKey(key) => {
+                let fields = vec![
+                    bitwarden_field("Public key", &key.public_key, false),
+                    bitwarden_field("Private key", &key.private_key, true),
+                    bitwarden_field("Passphrase", &key.passphrase, true),
+                ];
+                let mut item = bitwarden_item(id, 2, &entry.title, "", false, Value::Null, fields);
+                item["secureNote"] = json!({ "type": 0 });
+                item
+            }
+            ItemContent::ApiToken(token) => {
+                let fields = vec![
+                    bitwarden_field("Service", &token.service, false),
+                    bitwarden_field("Token", &token.token, true),
+                    bitwarden_field("Expires", &token.expires, false),
+                ];
+                let mut item = bitwarden_item(id, 2, &entry.title, "", false, Value::Null, fields);
+                item["secureNote"] = json!({ "type": 0 });
+                item
+            }
+        };
+        entries.push(item);
+    }
+
+    let folders: Vec<Value> = folders.iter().enumerate().map(|(index, name)| json!({ "id": bitwarden_id(index + 1), "name": name })).collect();
+    let export = json!({ "encrypted": false, "folders": folders, "items": entries });
+    let data = serde_json::to_vec_pretty(&export).map_err(|e| format!("Failed to write the JSON: {}", e))?;
+    Ok(Export { data, credentials: credentials.len(), items: items.len(), lost: Vec::new() })
+}
+
+//"name: value" lines, the custom fields column of the Bitwarden CSV
+fn field_lines(fields: &[(String, String)]) -> String {
+    fields.iter().map(|(name, value)| format!("{}: {}", name, value)).collect::<Vec<String>>().join("\n")
+}
+
+fn bitwarden_csv(credentials: &[Credential], items: &[Item]) -> Result<Export, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let write_error = |e: csv::Error| format!("Failed to write the CSV: {}", e);
+    writer
+        .write_record(["folder", "favorite", "type", "name", "notes", "fields", "reprompt", "login_uri", "login_username", "login_password", "login_totp"])
+        .map_err(write_error)?;
+    for credential in credentials {
+        let (folder, tags) = folder(credential);
+        let fields: Vec<(String, String)> = tags.into_iter().map(|tags| ("Tags".to_string(), tags)).collect();
+        writer
+            .write_record([
+                folder.as_str(),
+                if credential.favourite { "1" } else { "" },
+                "login",
+                &credential.site,
+                &credential.notes,
+                &field_lines(&fields),
+                "0",
+                &credential.url,
+                &credential.site_username,
+                &credential.site_password,
+                &credential.otp,
+            ])
+            .map_err(write_error)?;
+    }
+    //The CSV only knows logins and notes, the other items are notes holding their fields
+    for item in items {
+        let (notes, fields) = match &item.content {
+            ItemContent::SecureNote(note) => (note.text.clone(), Vec::new()),
+            content => (String::new(), content.fields().into_iter().map(|field| (field.label, field.value)).collect()),
+        };
+        writer
+            .write_record(["", "", "note", &item.title, &notes, &field_lines(&fields), "0", "", "", "", ""])
+            .map_err(write_error)?;
+    }
+    let data = writer.into_inner().map_err(|e| format!("Failed to write the CSV: {}", e))?;
+    Ok(Export { data, credentials: credentials.len(), items: items.len(), lost: Vec::new() })
+}
+
+fn chrome_csv(credentials: &[Credential], items: &[Item]) -> Result<Export, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let write_error = |e: csv::Error| format!("Failed to write the CSV: {}", e);
+    writer.write_record(["name", "url", "username", "password", "note"]).map_err(write_error)?;
+    for credential in credentials {
+        writer
+            .write_record([&credential.site, &credential.url, &credential.site_username, &credential.site_password, &credential.notes])
+            .map_err(write_error)?;
+    }
+    let data = writer.into_inner().map_err(|e| format!("Failed to write the CSV: {}", e))?;
+
+    let mut lost = Vec::new();
+    let counted = |check: fn(&Credential) -> bool| credentials.iter().filter(|credential| check(credential)).count();
+    for (count, what) in [
+        (items.len(), "items"),
+        (counted(|credential| !credential.otp.is_empty()), "2FA secrets"),
+        (counted(|credential| !credential.tags.is_empty()), "tag lists"),
+        (counted(|credential| credential.favourite), "favourite marks"),
+    ] {
+        if count > 0 {
+            lost.push(format!("{} {}", count, what));
+        }
+    }
+    Ok(Export { data, credentials: credentials.len(), items: 0, lost })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Card, SecureNote};
+
+    fn credential(site: &str, tags: &str, otp: &str, favourite: bool) -> Credential {
+        Credential {
+            site: site.to_string(),
+            site_username: "alice".to_string(),
+            site_password: format!("{} password", site),
+            url: format!("https://{}/login", site),
+            notes: "line one\nline \"two\", quoted".to_string(),
+            tags: tags.to_string(),
+            favourite,
+            otp: otp.to_string(),
+            ..Credential::default()
+        }
+    }
+
+    fn vault() -> (Vec<Credential>, Vec<Item>) {
+        let credentials = vec![
+            credential("mail.example", "work, personal", "otpauth://totp/mail?secret=JBSWY3DPEHPK3PXP", true),
+            credential("bank.example", "", "", false),
+            credential("forum.example", "work", "", false),
+        ];
+        let items = vec![
+            Item { id: 1, title: "Wifi".to_string(), content: ItemContent::SecureNote(SecureNote { text: "the code".to_string() }) },
+            Item {
+                id: 2,
+                title: "Visa".to_string(),
+                content: ItemContent::Card(Card { cardholder: "Alice".to_string(), number: "4111111111111111".to_string(), expiry: "03/27".to_string(), security_code: "123".to_string() }),
+            },
+        ];
+        (credentials, items)
+    }
+
+    fn csv_records(data: &[u8]) -> Vec<Vec<String>> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(data)
+            .records()
+            .map(|record| record.unwrap().iter().map(|field| field.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn tags_split_into_folder_and_list() {
+        assert_eq!(folder(&credential("a", "", "", false)), (String::new(), None));
+        assert_eq!(folder(&credential("a", " , ", "", false)), (String::new(), None));
+        assert_eq!(folder(&credential("a", " work ", "", false)), ("work".to_string(), None));
+        assert_eq!(folder(&credential("a", "work, ,personal", "", false)), ("work".to_string(), Some("work, personal".to_string())));
+    }
+
+    #[test]
+    fn card_expiry_to_month_and_year() {
+        assert_eq!(card_expiry("03/27"), (json!("3"), json!("2027")));
+        assert_eq!(card_expiry("12 / 2031"), (json!("12"), json!("2031")));
+        assert_eq!(card_expiry("/"), (Value::Null, Value::Null));
+        assert_eq!(card_expiry("0327"), (Value::Null, Value::Null));
+        assert_eq!(card_expiry(""), (Value::Null, Value::Null));
+    }
+
+    #[test]
+    fn bitwarden_json_layout() {
+        let (credentials, items) = vault();
+        let exported = export(ExportLayout::BitwardenJson, &credentials, &items).unwrap();
+        assert_eq!((exported.credentials, exported.items), (3, 2));
+        assert!(exported.lost.is_empty());
+
+        let json: Value = serde_json::from_slice(&exported.data).unwrap();
+        assert_eq!(json["encrypted"], false);
+        assert_eq!(json["folders"], json!([{ "id": bitwarden_id(1), "name": "work" }]));
+        let entries = json["items"].as_array().unwrap();
+        assert_eq!(entries.len(), 5);
+
+        let mail = &entries[0];
+        assert_eq!((mail["type"].clone(), mail["name"].clone(), mail["favorite"].clone()), (json!(1), json!("mail.example"), json!(true)));
+        assert_eq!(mail["folderId"], json!(bitwarden_id(1)));
+        assert_eq!(mail["fields"], json!([{ "name": "Tags", "value": "work, personal", "type": 0, "linkedId": null }]));
+        assert_eq!(mail["notes"], json!("line one\nline \"two\", quoted"));
+        assert_eq!(mail["login"]["uris"], json!([{ "match": null, "uri": "https://mail.example/login" }]));
+        assert_eq!(mail["login"]["username"], "alice");
+        assert_eq!(mail["login"]["password"], "mail.example password");
+        assert_eq!(mail["login"]["totp"], "otpauth://totp/mail?secret=JBSWY3DPEHPK3PXP");
+
+        //No tag: no folder, the same tag: the same folder
+        assert_eq!((entries[1]["folderId"].clone(), entries[1]["login"]["totp"].clone()), (Value::Null, Value::Null));
+        assert_eq!(entries[2]["folderId"], json!(bitwarden_id(1)));
+        assert_eq!(entries[2]["fields"], json!([]));
+
+        assert_eq!((entries[3]["type"].clone(), entries[3]["notes"].clone()), (json!(2), json!("the code")));
+        let card = &entries[4]["card"];
+        assert_eq!(entries[4]["type"], 3);
+        assert_eq!((card["expMonth"].clone(), card["expYear"].clone()), (json!("3"), json!("2027")));
+        assert_eq!((card["number"].clone(), card["code"].clone()), (json!("4111111111111111"), json!("123")));
+    }
+
+    #[test]
+    fn bitwarden_csv_layout() {
+        let (credentials, items) = vault();
+        let exported = export(ExportLayout::BitwardenCsv, &credentials, &items).unwrap();
+        assert_eq!((exported.credentials, exported.items), (3, 2));
+        assert!(exported.lost.is_empty());
+
+        let records = csv_records(&exported.data);
+        assert_eq!(records.len(), 6);
+        assert_eq!(
+            records[0],
+            ["folder", "favorite", "type", "name", "notes", "fields", "reprompt", "login_uri", "login_username", "login_password", "login_totp"]
+        );
+        assert_eq!(
+            records[1],
+            [
+                "work",
+                "1",
+                "login",
+                "mail.example",
+                "line one\nline \"two\", quoted",
+                "Tags: work, personal",
+                "0",
+                "https://mail.example/login",
+                "alice",
+                "mail.example password",
+                "otpauth://totp/mail?secret=JBSWY3DPEHPK3PXP",
+            ]
+        );
+        assert_eq!(&records[2][..3], ["", "", "login"]);
+        assert_eq!(records[3][0], "work");
+        assert_eq!(records[4], ["", "", "note", "Wifi", "the code", "", "0", "", "", "", ""]);
+        assert_eq!(records[5][..5], ["", "", "note", "Visa", ""]);
+        assert_eq!(records[5][5], "Cardholder: Alice\nNumber: 4111111111111111\nExpiry (MM/YY): 03/27\nSecurity code: 123");
+    }
+
+    #[test]
+    fn chrome_csv_layout_lists_what_is_lost() {
+        let (credentials, items) = vault();
+        let exported = export(ExportLayout::ChromeCsv, &credentials, &items).unwrap();
+        assert_eq!((exported.credentials, exported.items), (3, 0));
+        assert_eq!(exported.lost, ["2 items", "1 2FA secrets", "2 tag lists", "1 favourite marks"]);
+        assert_eq!(
+            exported.summary(),
+            "3 credentials and 0 items, not included in this layout: 2 items, 1 2FA secrets, 2 tag lists, 1 favourite marks"
+        );
+
+        let records = csv_records(&exported.data);
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0], ["name", "url", "username", "password", "note"]);
+        assert_eq!(records[1], ["mail.example", "https://mail.example/login", "alice", "mail.example password", "line one\nline \"two\", quoted"]);
+
+        //Nothing lost when the vault only holds plain logins
+        let exported = export(ExportLayout::ChromeCsv, &credentials[1..2], &[]).unwrap();
+        assert!(exported.lost.is_empty());
+        assert_eq!(exported.summary(), "1 credentials and 0 items");
+    }
+}
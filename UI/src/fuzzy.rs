@@ -0,0 +1,331 @@
+//Fuzzy extractor (code-offset secure sketch) releasing the key that wraps the vault key.
+//The features are the signs of random projections of the ridge orientation map, centred on the
+//centroid of the print so the position of the finger on the sensor does not matter. A random
+//secret is encoded with a repetition code and XORed with the signs of the projections that
+//kept the same sign with the largest margin in every impression. The selection only depends on
+//the magnitude of a projection, never on its sign, so the stored offset says nothing about the
+//codeword, and an empty or constant probe gives a random secret. Only a salt, the selection and
+//this offset are stored as helper data. The copies of a secret bit are spread over strong and
+//weak projections. A probe close enough to the enrolled finger flips few signs, the majority
+//vote over the copies of every bit corrects them and gives back the secret.
+//The sensor is small so the features hold less entropy than the 128 bits of the secret, the
+//key is therefore stretched with Argon2id to make every offline guess costly.
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+//Marks the helper data of this format, older helper data is refused
+const MAGIC: &[u8; 4] = b"BGFE";
+//Size in pixels of the orientation cells
+const CELL: usize = 8;
+//Random projections drawn for every template, the most reliable ones are kept
+const CANDIDATES: usize = 2048;
+//Bits of the secret and repetitions of each bit in the codeword
+const SECRET_BITS: usize = 128;
+const REPEAT: usize = 3;
+const SALT_LEN: usize = 16;
+//Argon2id cost of the key: 19 MiB, 2 passes, 1 lane
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_PASSES: u32 = 2;
+const ARGON2_LANES: u32 = 1;
+
+//Ridge orientation of every cell, after moving the centroid of the points to the centre of
+//the image, minus the mean orientation so that a projection is as likely to be positive as
+//negative
+pub fn features(points: &[(usize, usize)], height: usize, width: usize) -> Vec<f64> {
+    let rows = height.div_ceil(CELL);
+    let cols = width.div_ceil(CELL);
+    let mut cells = vec![0f64; rows * cols * 2];
+    if points.is_empty() {
+        return cells;
+    }
+    let mut ridges = vec![false; height * width];
+    for &(x, y) in points {
+        if x < height && y < width {
+            ridges[x * width + y] = true;
+        }
+    }
+    let count = points.len() as f64;
+    let cx = points.iter().map(|&(x, _)| x as f64).sum::<f64>() / count;
+    let cy = points.iter().map(|&(_, y)| y as f64).sum::<f64>() / count;
+
+    for &(x, y) in points {
+        //Doubled angle so that opposite directions along a ridge add up instead of cancelling
+        let (mut cos, mut sin) = (0.0, 0.0);
+        for dx in -2i64..=2 {
+            for dy in -2i64..=2 {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if (dx, dy) == (0, 0) || nx < 0 || ny < 0 || nx >= height as i64 || ny >= width as i64 {
+                    continue;
+                }
+                if ridges[nx as usize * width + ny as usize] {
+                    let angle = (dy as f64).atan2(dx as f64);
+                    cos += (2.0 * angle).cos();
+                    sin += (2.0 * angle).sin();
+                }
+            }
+        }
+        let x = x as f64 - cx + (height / 2) as f64;
+        let y = y as f64 - cy + (width / 2) as f64;
+        if x >= 0.0 && y >= 0.0 && x < height as f64 && y < width as f64 {
+            let cell = (x as usize / CELL) * cols + y as usize / CELL;
+            cells[cell * 2] += cos;
+            cells[cell * 2 + 1] += sin;
+        }
+    }
+
+    //The mean orientation is the same for most prints and would bias every projection
+    let total = (rows * cols) as f64;
+    let mean_cos = cells.iter().step_by(2).sum::<f64>() / total;
+    let mean_sin = cells.iter().skip(1).step_by(2).sum::<f64>() / total;
+    for (i, cell) in cells.iter_mut().enumerate() {
+        *cell -= if i % 2 == 0 { mean_cos } else { mean_sin };
+    }
+    cells
+}
+
+//Random +1/-1 weights of a projection, drawn from the salt so they are never stored
+fn projection(salt: &[u8], index: usize, len: usize) -> Vec<f64> {
+    let mut weights = Vec::with_capacity(len);
+    let mut block = 0u32;
+    while weights.len() < len {
+        let mut hasher = Sha256::new();
+        hasher.update(b"bioguard-fuzzy-projection");
+        hasher.update(salt);
+        hasher.update((index as u32).to_le_bytes());
+        hasher.update(block.to_le_bytes());
+        let bytes = hasher.finalize();
+        for bit in 0..256 {
+            if weights.len() == len {
+                break;
+            }
+            weights.push(if bytes[bit / 8] & (1 << (bit % 8)) != 0 { 1.0 } else { -1.0 });
+        }
+        block += 1;
+    }
+    weights
+}
+
+fn project(features: &[f64], salt: &[u8], indices: impl Iterator<Item = usize>) -> Vec<f64> {
+    indices
+        .map(|index| {
+            projection(salt, index, features.len())
+                .iter()
+                .zip(features)
+                .map(|(weight, feature)| weight * feature)
+                .sum()
+        })
+        .collect()
+}
+
+//Projections keeping the same sign in every impression, by decreasing smallest magnitude
+fn reliable_projections(values: &[Vec<f64>], count: usize) -> Vec<usize> {
+    let mut margins: Vec<(usize, f64)> = (0..CANDIDATES)
+        .map(|index| {
+            let positive = values.iter().all(|value| value[index] > 0.0);
+            let negative = values.iter().all(|value| value[index] < 0.0);
+            let smallest = values.iter().map(|value| value[index].abs()).fold(f64::INFINITY, f64::min);
+            (index, if positive || negative { smallest } else { -1.0 })
+        })
+        .collect();
+    margins.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    margins.into_iter().take(count).map(|(index, _)| index).collect()
+}
+
+fn derive_key(salt: &[u8], secret: &[bool]) -> [u8; 32] {
+    let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_PASSES, ARGON2_LANES, Some(32)).unwrap();
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+    let mut key = [0u8; 32];
+    argon2.hash_password_into(&pack(secret), salt, &mut key).expect("Argon2id key derivation failed");
+    key
+}
+
+fn pack(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| chunk.iter().enumerate().fold(0u8, |byte, (i, &bit)| byte | ((bit as u8) << i)))
+        .collect()
+}
+
+fn unpack(bytes: &[u8], len: usize) -> Vec<bool> {
+    (0..len).map(|i| bytes[i / 8] & (1 << (i % 8)) != 0).collect()
+}
+
+//Helper layout: MAGIC, salt, selected projection count (u16 LE), projections (u16 LE each),
+//packed offset bits
+pub fn enroll(impressions: &[Vec<(usize, usize)>], height: usize, width: usize) -> ([u8; 32], Vec<u8>) {
+    let mut rng = rand::thread_rng();
+    let mut salt = [0u8; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    let mut secret_bytes = [0u8; SECRET_BITS / 8];
+    rng.fill_bytes(&mut secret_bytes);
+    let secret = unpack(&secret_bytes, SECRET_BITS);
+
+    let values: Vec<Vec<f64>> = impressions
+        .iter()
+        .map(|points| project(&features(points, height, width), &salt, 0..CANDIDATES))
+        .collect();
+    let selected = reliable_projections(&values, SECRET_BITS * REPEAT);
+
+    //Reference sign of every selected projection: the sign of its mean over the impressions.
+    //Projection i carries bit i % SECRET_BITS of the secret.
+    let offset: Vec<bool> = selected
+        .iter()
+        .enumerate()
+        .map(|(i, &index)| {
+            let sum: f64 = values.iter().map(|value| value[index]).sum();
+            secret[i % SECRET_BITS] ^ (sum > 0.0)
+        })
+        .collect();
+
+    let mut helper = MAGIC.to_vec();
+    helper.extend_from_slice(&salt);
+    helper.extend_from_slice(&(selected.len() as u16).to_le_bytes());
+    for &index in &selected {
+        helper.extend_from_slice(&(index as u16).to_le_bytes());
+    }
+    helper.extend_from_slice(&pack(&offset));
+
+    (derive_key(&salt, &secret), helper)
+}
+
+//Gives back the enrolled key if the probe is close enough, a wrong key otherwise.
+//None when the helper data is not in this format.
+pub fn reproduce(points: &[(usize, usize)], helper: &[u8], height: usize, width: usize) -> Option<[u8; 32]> {
+    if !helper.starts_with(MAGIC) {
+        return None;
+    }
+    let mut offset_start = MAGIC.len();
+    let salt = helper.get(offset_start..offset_start + SALT_LEN)?;
+    offset_start += SALT_LEN;
+    let count = u16::from_le_bytes(helper.get(offset_start..offset_start + 2)?.try_into().ok()?) as usize;
+    offset_start += 2;
+    if count != SECRET_BITS * REPEAT {
+        return None;
+    }
+    let mut selected = Vec::with_capacity(count);
+    for _ in 0..count {
+        let bytes = helper.get(offset_start..offset_start + 2)?;
+        let index = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+        if index >= CANDIDATES {
+            return None;
+        }
+        selected.push(index);
+        offset_start += 2;
+    }
+    let offset_bytes = helper.get(offset_start..)?;
+    if offset_bytes.len() * 8 < count {
+        return None;
+    }
+    let offset = unpack(offset_bytes, count);
+
+    let values = project(&features(points, height, width), salt, selected.into_iter());
+    let secret: Vec<bool> = (0..SECRET_BITS)
+        .map(|bit| {
+            let ones = (0..REPEAT)
+                .map(|j| j * SECRET_BITS + bit)
+                .filter(|&i| offset[i] ^ (values[i] > 0.0))
+                .count();
+            ones * 2 > REPEAT
+        })
+        .collect();
+
+    Some(derive_key(salt, &secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const HEIGHT: usize = 80;
+    const WIDTH: usize = 64;
+
+    //Skeleton-like ridges: curved parallel lines whose direction, spacing and bending depend
+    //on the finger
+    fn finger(seed: u64) -> Vec<(usize, usize)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let angle: f64 = rng.gen_range(0.0..std::f64::consts::PI);
+        let period: f64 = rng.gen_range(6.0..9.0);
+        let bend: f64 = rng.gen_range(2.0..8.0);
+        let wave: f64 = rng.gen_range(0.03..0.12);
+        let (cx, cy) = (rng.gen_range(25.0..55.0), rng.gen_range(20.0..44.0));
+        let mut points = Vec::new();
+        for x in 4..HEIGHT - 4 {
+            for y in 4..WIDTH - 4 {
+                let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+                if dx * dx / 900.0 + dy * dy / 500.0 > 1.0 {
+                    continue;
+                }
+                let along = dx * angle.cos() + dy * angle.sin() + bend * (wave * (dy * angle.cos() - dx * angle.sin())).sin();
+                if (along / period).rem_euclid(1.0) < 1.0 / period {
+                    points.push((x, y));
+                }
+            }
+        }
+        points
+    }
+
+    //Another capture of the same finger: moved on the sensor, some points lost, some noise
+    fn impression(points: &[(usize, usize)], seed: u64) -> Vec<(usize, usize)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (sx, sy): (i64, i64) = (rng.gen_range(-3..=3), rng.gen_range(-3..=3));
+        let mut moved: Vec<(usize, usize)> = points
+            .iter()
+            .filter(|_| rng.gen_bool(0.9))
+            .map(|&(x, y)| (x as i64 + sx, y as i64 + sy))
+            .filter(|&(x, y)| x >= 0 && y >= 0 && x < HEIGHT as i64 && y < WIDTH as i64)
+            .map(|(x, y)| (x as usize, y as usize))
+            .collect();
+        for _ in 0..points.len() / 20 {
+            moved.push((rng.gen_range(0..HEIGHT), rng.gen_range(0..WIDTH)));
+        }
+        moved
+    }
+
+    fn enroll_finger(seed: u64) -> ([u8; 32], Vec<u8>) {
+        let print = finger(seed);
+        let impressions: Vec<Vec<(usize, usize)>> = (0..3).map(|i| impression(&print, seed * 10 + i)).collect();
+        enroll(&impressions, HEIGHT, WIDTH)
+    }
+
+    #[test]
+    fn genuine_probe_releases_the_key() {
+        for seed in 1..4 {
+            let (key, helper) = enroll_finger(seed);
+            let probe = impression(&finger(seed), seed * 10 + 9);
+            assert_eq!(reproduce(&probe, &helper, HEIGHT, WIDTH), Some(key));
+        }
+    }
+
+    #[test]
+    fn impostor_probe_does_not_release_the_key() {
+        let (key, helper) = enroll_finger(1);
+        for seed in 2..5 {
+            let probe = impression(&finger(seed), seed * 10 + 9);
+            assert_ne!(reproduce(&probe, &helper, HEIGHT, WIDTH), Some(key));
+        }
+    }
+
+    #[test]
+    fn empty_probe_does_not_release_the_key() {
+        let (key, helper) = enroll_finger(1);
+        assert_ne!(reproduce(&[], &helper, HEIGHT, WIDTH), Some(key));
+    }
+
+    #[test]
+    fn offset_is_not_the_codeword() {
+        //With constant features the offset would be the repeated secret: every group equal
+        let (_, helper) = enroll_finger(2);
+        let start = MAGIC.len() + SALT_LEN + 2 + SECRET_BITS * REPEAT * 2;
+        let offset = unpack(&helper[start..], SECRET_BITS * REPEAT);
+        let mixed = (0..SECRET_BITS).filter(|&bit| (1..REPEAT).any(|j| offset[j * SECRET_BITS + bit] != offset[bit])).count();
+        assert!(mixed > SECRET_BITS / 2, "{} groups of the offset are mixed", mixed);
+    }
+
+    #[test]
+    fn helper_of_another_format_is_refused() {
+        let (_, helper) = enroll_finger(1);
+        assert_eq!(reproduce(&finger(1), &helper[MAGIC.len()..], HEIGHT, WIDTH), None);
+    }
+}
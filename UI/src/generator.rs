@@ -0,0 +1,86 @@
+//Password generator of the credentials form. Every character or word is drawn from the
+//operating system CSPRNG. The diceware wordlist is the BIP-39 English list: 2048 words,
+//11 bits each, so the default 5 words give 55 bits.
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::models::{GeneratorMode, GeneratorSettings};
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{}~";
+//Characters easily mistaken for one another when read or typed by hand
+const AMBIGUOUS: &str = "Il1|O0o";
+const CONSONANTS: &[u8] = b"bcdfghjkmnprstvz";
+const VOWELS: &[u8] = b"aeiu";
+const WORDLIST: &str = include_str!("wordlist.txt");
+
+pub const MIN_LENGTH: usize = 8;
+pub const MAX_LENGTH: usize = 64;
+pub const MIN_WORDS: usize = 3;
+pub const MAX_WORDS: usize = 12;
+
+//None when no character class is selected
+pub fn random(length: usize, lowercase: bool, uppercase: bool, digits: bool, symbols: bool, exclude_ambiguous: bool) -> Option<String> {
+    let classes: Vec<Vec<char>> = [(lowercase, LOWERCASE), (uppercase, UPPERCASE), (digits, DIGITS), (symbols, SYMBOLS)]
+        .iter()
+        .filter(|(selected, _)| *selected)
+        .map(|(_, class)| class.chars().filter(|c| !exclude_ambiguous || !AMBIGUOUS.contains(*c)).collect())
+        .collect();
+    if classes.is_empty() {
+        return None;
+    }
+    let all: Vec<char> = classes.concat();
+    let length = length.max(classes.len());
+
+    let mut rng = OsRng;
+    //One character of every selected class, the rest from all of them
+    let mut password: Vec<char> = classes.iter().map(|class| *class.choose(&mut rng).unwrap()).collect();
+    while password.len() < length {
+        password.push(*all.choose(&mut rng).unwrap());
+    }
+    password.shuffle(&mut rng);
+    Some(password.into_iter().collect())
+}
+
+//Alternating consonants and vowels starting with a capital, ending with two digits
+pub fn pronounceable(length: usize) -> String {
+    let mut rng = OsRng;
+    let letters = length.max(MIN_LENGTH) - 2;
+    let mut password: String = (0..letters)
+        .map(|i| {
+            let set = if i % 2 == 0 { CONSONANTS } else { VOWELS };
+            *set.choose(&mut rng).unwrap() as char
+        })
+        .collect();
+    password[..1].make_ascii_uppercase();
+    password.push_str(&format!("{:02}", rng.gen_range(0..100)));
+    password
+}
+
+pub fn diceware(words: usize, separator: &str) -> String {
+    let wordlist: Vec<&str> = WORDLIST.lines().filter(|word| !word.is_empty()).collect();
+    let mut rng = OsRng;
+    (0..words.max(MIN_WORDS))
+        .map(|_| *wordlist.choose(&mut rng).unwrap())
+        .collect::<Vec<&str>>()
+        .join(separator)
+}
+
+pub fn generate(settings: &GeneratorSettings) -> Option<String> {
+    let length = (settings.length.round() as usize).clamp(MIN_LENGTH, MAX_LENGTH);
+    match settings.mode {
+        GeneratorMode::Random => random(
+            length,
+            settings.lowercase,
+            settings.uppercase,
+            settings.digits,
+            settings.symbols,
+            settings.exclude_ambiguous,
+        ),
+        GeneratorMode::Pronounceable => Some(pronounceable(length)),
+        GeneratorMode::Diceware => Some(diceware((settings.words.round() as usize).clamp(MIN_WORDS, MAX_WORDS), "-")),
+    }
+}
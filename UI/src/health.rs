@@ -0,0 +1,248 @@
+//Vault health report over the credentials of a user: passwords reused across sites, weak
+//passwords (see strength.rs) and passwords not changed for a number of days. Only sites and
+//usernames are reported, never the passwords, so the JSON export can go to a security review.
+use crate::models::{Credential, HealthIssue};
+use crate::strength;
+use serde::Serialize;
+use std::collections::HashMap;
+use time::format_description::{self, FormatItem};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+//Fair or below
+const WEAK_SCORE: u8 = 2;
+const SECONDS_PER_DAY: u64 = 86400;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialRef {
+    pub id: i32,
+    pub site: String,
+    pub site_username: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WeakPassword {
+    #[serde(flatten)]
+    pub credential: CredentialRef,
+    pub score: u8,
+    pub strength: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OldPassword {
+    #[serde(flatten)]
+    pub credential: CredentialRef,
+    pub password_changed_at: String,
+    pub age_days: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub username: String,
+    //Unix time
+    pub generated_at: u64,
+    pub max_age_days: u64,
+    pub total: usize,
+    //Groups of credentials sharing the same password
+    pub reused: Vec<Vec<CredentialRef>>,
+    pub weak: Vec<WeakPassword>,
+    pub old: Vec<OldPassword>,
+}
+
+fn credential_ref(cred: &Credential) -> CredentialRef {
+    CredentialRef {
+        id: cred.id,
+        site: cred.site.clone(),
+        site_username: cred.site_username.clone(),
+    }
+}
+
+//"YYYY-MM-DD HH:MM:SS" as returned by sqlite datetime()
+fn sqlite_format() -> Vec<FormatItem<'static>> {
+    format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]").expect("Invalid date format description")
+}
+
+//Unix time of a UTC date as returned by sqlite datetime()
+pub fn parse_utc(date: &str) -> Option<u64> {
+    let date = PrimitiveDateTime::parse(date.trim(), &sqlite_format()).ok()?;
+    u64::try_from(date.assume_utc().unix_timestamp()).ok()
+}
+
+//Inverse of parse_utc, empty when the time is out of range
+pub fn format_utc(time: u64) -> String {
+    i64::try_from(time)
+        .ok()
+        .and_then(|time| OffsetDateTime::from_unix_timestamp(time).ok())
+        .and_then(|date| date.format(&sqlite_format()).ok())
+        .unwrap_or_default()
+}
+
+pub fn report(username: &str, credentials: &[Credential], max_age_days: u64, now: u64) -> HealthReport {
+    let mut by_password: HashMap<&str, Vec<&Credential>> = HashMap::new();
+    for cred in credentials.iter().filter(|cred| !cred.site_password.is_empty()) {
+        by_password.entry(cred.site_password.as_str()).or_default().push(cred);
+    }
+    let mut reused: Vec<Vec<CredentialRef>> = by_password
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|group| group.into_iter().map(credential_ref).collect())
+        .collect();
+    reused.sort_by(|a, b| a[0].site.cmp(&b[0].site));
+
+    //The score estimated when the credentials were loaded
+    let weak = credentials
+        .iter()
+        .filter(|cred| cred.strength <= WEAK_SCORE)
+        .map(|cred| WeakPassword {
+            credential: credential_ref(cred),
+            score: cred.strength,
+            strength: strength::label(cred.strength).to_string(),
+        })
+        .collect();
+
+    let old = credentials
+        .iter()
+        .filter_map(|cred| {
+            let changed = parse_utc(&cred.password_changed_at)?;
+            let age_days = now.saturating_sub(changed) / SECONDS_PER_DAY;
+            if age_days < max_age_days {
+                return None;
+            }
+            Some(OldPassword {
+                credential: credential_ref(cred),
+                password_changed_at: cred.password_changed_at.clone(),
+                age_days,
+            })
+        })
+        .collect();
+
+    HealthReport {
+        username: username.to_string(),
+        generated_at: now,
+        max_age_days,
+        total: credentials.len(),
+        reused,
+        weak,
+        old,
+    }
+}
+
+impl HealthReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} credentials: {} reused passwords shared by {} sites, {} weak, {} not changed for {} days",
+            self.total,
+            self.reused.len(),
+            self.reused.iter().map(|group| group.len()).sum::<usize>(),
+            self.weak.len(),
+            self.old.len(),
+            self.max_age_days
+        )
+    }
+
+    //One row per problem, a credential can appear several times
+    pub fn issues(&self) -> Vec<HealthIssue> {
+        let mut issues = Vec::new();
+        for group in &self.reused {
+            for cred in group {
+                let others: Vec<&str> = group.iter().filter(|other| other.id != cred.id).map(|other| other.site.as_str()).collect();
+                issues.push(HealthIssue {
+                    id: cred.id,
+                    site: cred.site.clone(),
+                    site_username: cred.site_username.clone(),
+                    issue: "Reused".to_string(),
+                    detail: format!("Same password as {}", others.join(", ")),
+                });
+            }
+        }
+        for weak in &self.weak {
+            issues.push(HealthIssue {
+                id: weak.credential.id,
+                site: weak.credential.site.clone(),
+                site_username: weak.credential.site_username.clone(),
+                issue: "Weak".to_string(),
+                detail: format!("{} password", weak.strength),
+            });
+        }
+        for old in &self.old {
+            issues.push(HealthIssue {
+                id: old.credential.id,
+                site: old.credential.site.clone(),
+                site_username: old.credential.site_username.clone(),
+                issue: "Old".to_string(),
+                detail: format!("Not changed for {} days, since {}", old.age_days, old.password_changed_at),
+            });
+        }
+        issues
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential(id: i32, site: &str, password: &str, password_changed_at: &str) -> Credential {
+        Credential {
+            id,
+            site: site.to_string(),
+            site_username: "alice".to_string(),
+            site_password: password.to_string(),
+            strength: strength::estimate(password).score,
+            password_changed_at: password_changed_at.to_string(),
+            ..Credential::default()
+        }
+    }
+
+    #[test]
+    fn sqlite_dates() {
+        for (date, time) in [("1970-01-01 00:00:00", 0), ("2023-11-14 22:13:20", 1_700_000_000), ("2024-02-29 12:00:00", 1_709_208_000)] {
+            assert_eq!(parse_utc(date), Some(time), "{}", date);
+            assert_eq!(format_utc(time), date);
+        }
+        assert_eq!(parse_utc(" 2024-02-29 12:00:00\n"), Some(1_709_208_000));
+        for invalid in ["", "2023-02-29 00:00:00", "2024-13-01 00:00:00", "2024-01-01", "2024-01-01T00:00:00", "1969-12-31 23:59:59"] {
+            assert_eq!(parse_utc(invalid), None, "{}", invalid);
+        }
+        assert_eq!(format_utc(u64::MAX), "");
+    }
+
+    #[test]
+    fn reused_weak_and_old() {
+        let now = parse_utc("2024-06-01 00:00:00").unwrap();
+        let credentials = [
+            credential(1, "mail", "Tr0ub4dor&3-horse-staple", "2024-05-01 00:00:00"),
+            credential(2, "forum", "Tr0ub4dor&3-horse-staple", "2023-01-01 00:00:00"),
+            credential(3, "bank", "password1", "2024-05-31 00:00:00"),
+            credential(4, "shop", "v8#Lq!2zW@p9rKx$", ""),
+        ];
+        let report = report("alice", &credentials, 90, now);
+        assert_eq!(report.total, 4);
+
+        let reused: Vec<Vec<i32>> = report.reused.iter().map(|group| group.iter().map(|cred| cred.id).collect()).collect();
+        assert_eq!(reused.len(), 1);
+        assert_eq!(reused[0].len(), 2);
+
+        let weak: Vec<i32> = report.weak.iter().map(|weak| weak.credential.id).collect();
+        assert_eq!(weak, [3]);
+        assert_eq!(report.weak[0].strength, strength::label(report.weak[0].score));
+
+        //No date is never reported as old
+        assert_eq!(report.old.len(), 1);
+        assert_eq!((report.old[0].credential.id, report.old[0].age_days), (2, 517));
+
+        let issues = report.issues();
+        assert_eq!(issues.iter().map(|issue| issue.issue.as_str()).collect::<Vec<_>>(), ["Reused", "Reused", "Weak", "Old"]);
+        assert!(report.to_json().unwrap().contains("\"max_age_days\": 90"));
+        assert!(!report.to_json().unwrap().contains("password1"));
+    }
+
+    #[test]
+    fn stored_strength_is_used() {
+        let mut cred = credential(1, "mail", "v8#Lq!2zW@p9rKx$", "");
+        cred.strength = 1;
+        assert_eq!(report("alice", &[cred], 90, 0).weak.len(), 1);
+    }
+}
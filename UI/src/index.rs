@@ -0,0 +1,251 @@
+//Minutia-triplet hashing used to narrow down the candidates of 1:N identification.
+//Each minutia forms triangles with its nearest neighbours, the sorted side lengths of a
+//triangle do not change with translation or rotation so once quantized, with the type of the
+//opposite minutiae, they make a key that both impressions of a finger should share. Only keys
+//sealed with a secret kept outside users.db are stored, so the database does not tell the
+//geometry of the prints. Measured penetration and accuracy are in INDEX.md.
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+
+use crate::crypto;
+
+//Number of nearest neighbours a minutia forms triangles with
+const NEIGHBOURS: usize = 3;
+//Size in pixels of the bins the triangle sides are quantized into
+const BIN_SIZE: f64 = 4.0;
+//Triangles smaller than this are mostly noise from the thinning
+const MIN_SIDE: f64 = 3.0;
+//Minutiae this close to the border of the image are mostly ridges cut by the sensor edge
+const MARGIN: usize = 5;
+
+//Row, column and whether the minutia is a bifurcation (a ridge ending otherwise)
+pub type Point = (usize, usize, bool);
+
+fn distance(a: Point, b: Point) -> f64 {
+    ((a.0 as f64 - b.0 as f64).powi(2) + (a.1 as f64 - b.1 as f64).powi(2)).sqrt()
+}
+
+//Packs the three quantized sides of a triangle in a single key, each side with the type of
+//the minutia facing it
+fn triangle_key(a: Point, b: Point, c: Point) -> Option<i64> {
+    let mut sides = [(distance(b, c), a.2), (distance(a, c), b.2), (distance(a, b), c.2)];
+    sides.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap().then(x.1.cmp(&y.1)));
+    if sides[0].0 < MIN_SIDE {
+        return None;
+    }
+    let q: Vec<i64> = sides.iter().map(|side| (side.0 / BIN_SIZE).floor() as i64).collect();
+    let types = sides.iter().enumerate().fold(0, |types, (i, side)| types | ((side.1 as i64) << (48 + i)));
+    Some(types | (q[0] << 32) | (q[1] << 16) | q[2])
+}
+
+//Computes the index keys of the minutiae of one impression of a rows x cols image. Neighbours
+//closer than MIN_SIDE are skipped, they are mostly the next points of the same ridge.
+pub fn triplet_keys(points: &[Point], rows: usize, cols: usize) -> HashSet<i64> {
+    let mut keys = HashSet::new();
+    let points: Vec<Point> = points
+        .iter()
+        .filter(|p| p.0 >= MARGIN && p.0 + MARGIN < rows && p.1 >= MARGIN && p.1 + MARGIN < cols)
+        .copied()
+        .collect();
+
+    for (i, &p) in points.iter().enumerate() {
+        let mut neighbours: Vec<(f64, Point)> = points
+            .iter()
+            .enumerate()
+            .filter(|&(j, &q)| j != i && distance(p, q) >= MIN_SIDE)
+            .map(|(_, &q)| (distance(p, q), q))
+            .collect();
+        neighbours.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+        neighbours.truncate(NEIGHBOURS);
+
+        for a in 0..neighbours.len() {
+            for b in (a + 1)..neighbours.len() {
+                if let Some(key) = triangle_key(p, neighbours[a].1, neighbours[b].1) {
+                    keys.insert(key);
+                }
+            }
+        }
+    }
+    keys
+}
+
+//Keys of a template are the union of the keys of its impressions
+pub fn template_keys(impressions: &[Vec<Point>], rows: usize, cols: usize) -> HashSet<i64> {
+    impressions.iter().flat_map(|points| triplet_keys(points, rows, cols)).collect()
+}
+
+//HMAC-SHA256 of every key with the index secret, truncated to fit the key column
+pub fn seal(secret: &[u8], keys: &HashSet<i64>) -> Vec<i64> {
+    keys.iter()
+        .map(|key| {
+            let mac = crypto::hmac::<Sha256>(secret, &key.to_le_bytes());
+            i64::from_le_bytes(mac[..8].try_into().unwrap())
+        })
+        .collect()
+}
+
+//Ranks the templates by the number of keys they share with the probe over the square root of
+//their number of keys, and keeps the best `limit`. Votes holds both counts for each template.
+//The probe being the same for all, this is the cosine of the key sets: raw counts favour the
+//templates enrolled from noisy impressions, they have many keys and share some with any probe.
+pub fn rank_candidates(votes: HashMap<i64, (usize, usize)>, limit: usize) -> Vec<i64> {
+    let mut ranked: Vec<(i64, f64)> = votes
+        .into_iter()
+        .map(|(id, (shared, keys))| (id, shared as f64 / (keys.max(1) as f64).sqrt()))
+        .collect();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    ranked.into_iter().take(limit).map(|(id, _)| id).collect()
+}
+
+//Id of a finger and the points of its impressions
+pub type IndexedFinger = (i64, Vec<Vec<Point>>);
+
+//Measures penetration rate (share of the database that still has to be fully matched)
+//against accuracy (share of probes whose own template is kept in the shortlist).
+//Every impression of a template with at least two impressions is a probe, its own template
+//being keyed from the other impressions only so the probe is not found trivially.
+pub fn report(templates: &[IndexedFinger], rows: usize, cols: usize, shortlists: &[usize]) -> Vec<(usize, f64, f64)> {
+    let all_keys: Vec<HashSet<i64>> = templates.iter().map(|(_, impressions)| template_keys(impressions, rows, cols)).collect();
+    let total = templates.len();
+    let mut results = Vec::new();
+
+    for &limit in shortlists {
+        let mut probes = 0;
+        let mut hits = 0;
+        let mut penetration = 0.0;
+
+        for (i, (id, impressions)) in templates.iter().enumerate() {
+            if impressions.len() < 2 {
+                continue;
+            }
+            for (k, points) in impressions.iter().enumerate() {
+                let probe = triplet_keys(points, rows, cols);
+                let others: Vec<Vec<Point>> = impressions.iter().enumerate().filter(|&(n, _)| n != k).map(|(_, points)| points.clone()).collect();
+                let own_keys = template_keys(&others, rows, cols);
+
+                let mut votes = HashMap::new();
+                for (j, (other_id, _)) in templates.iter().enumerate() {
+                    let keys = if i == j { &own_keys } else { &all_keys[j] };
+                    let shared = probe.intersection(keys).count();
+                    if shared > 0 {
+                        votes.insert(*other_id, (shared, keys.len()));
+                    }
+                }
+                let candidates = rank_candidates(votes, limit);
+
+                probes += 1;
+                penetration += candidates.len() as f64 / total as f64;
+                if candidates.contains(id) {
+                    hits += 1;
+                }
+            }
+        }
+
+        if probes > 0 {
+            results.push((limit, penetration / probes as f64, hits as f64 / probes as f64));
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROWS: usize = 80;
+    const COLS: usize = 64;
+
+    //Pseudo-random minutiae away from the border, the same for a given seed
+    fn finger(seed: u64) -> Vec<Point> {
+        let mut state = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let mut next = |bound: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 33) % bound) as usize
+        };
+        (0..12).map(|_| (8 + next(64), 8 + next(48), next(2) == 1)).collect()
+    }
+
+    fn shifted(points: &[Point], rows: usize, cols: usize) -> Vec<Point> {
+        points.iter().map(|&(row, col, bifurcation)| (row + rows, col + cols, bifurcation)).collect()
+    }
+
+    #[test]
+    fn keys_survive_translation_and_rotation() {
+        let points = finger(1);
+        let keys = triplet_keys(&points, ROWS, COLS);
+        assert!(keys.len() > 10);
+        assert_eq!(triplet_keys(&shifted(&points, 2, 3), ROWS + 2, COLS + 3), keys);
+
+        //A quarter turn keeps every distance exactly
+        let turned: Vec<Point> = points.iter().map(|&(row, col, bifurcation)| (col, ROWS - 1 - row, bifurcation)).collect();
+        assert_eq!(triplet_keys(&turned, COLS, ROWS), keys);
+
+        let retyped: Vec<Point> = points.iter().map(|&(row, col, bifurcation)| (row, col, !bifurcation)).collect();
+        assert_ne!(triplet_keys(&retyped, ROWS, COLS), keys);
+    }
+
+    #[test]
+    fn border_points_are_ignored() {
+        let points = vec![(20, 20, false), (20, 30, true), (32, 24, false)];
+        let keys = triplet_keys(&points, ROWS, COLS);
+        assert_eq!(keys.len(), 1);
+
+        let mut noisy = points.clone();
+        noisy.extend([(2, 30, false), (20, COLS - 3, true), (ROWS - 1, 10, false)]);
+        assert_eq!(triplet_keys(&noisy, ROWS, COLS), keys);
+        assert!(triplet_keys(&points[..2], ROWS, COLS).is_empty());
+    }
+
+    #[test]
+    fn sealed_keys_depend_on_the_secret() {
+        let keys = triplet_keys(&finger(2), ROWS, COLS);
+        assert!(!keys.is_empty());
+
+        let mut sealed = seal(b"first secret", &keys);
+        let mut again = seal(b"first secret", &keys);
+        sealed.sort();
+        again.sort();
+        assert_eq!(sealed, again);
+
+        let other = seal(b"second secret", &keys);
+        assert!(other.iter().all(|key| !sealed.contains(key)));
+        assert!(keys.iter().all(|key| !sealed.contains(key)));
+    }
+
+    #[test]
+    fn candidates_by_votes_then_id() {
+        let votes = HashMap::from([(7, (3, 9)), (2, (6, 9)), (9, (6, 9)), (4, (1, 4))]);
+        assert_eq!(rank_candidates(votes.clone(), 3), [2, 9, 7]);
+        assert_eq!(rank_candidates(votes.clone(), 10), [2, 9, 7, 4]);
+        assert!(rank_candidates(votes, 0).is_empty());
+        assert!(rank_candidates(HashMap::new(), 5).is_empty());
+
+        //A template with many keys does not win on its size alone
+        let votes = HashMap::from([(1, (4, 16)), (2, (6, 100))]);
+        assert_eq!(rank_candidates(votes, 2), [1, 2]);
+    }
+
+    #[test]
+    fn report_on_distinct_fingers() {
+        //Three impressions of each finger, shifted and missing one minutia each
+        let templates: Vec<IndexedFinger> = (0..4)
+            .map(|id| {
+                let points = finger(id as u64);
+                let impressions = (0..3).map(|k| shifted(&[&points[..k], &points[k + 1..]].concat(), k, 1)).collect();
+                (id, impressions)
+            })
+            .collect();
+        let results = report(&templates, ROWS + 3, COLS + 1, &[1, 2]);
+        assert_eq!(results[0], (1, 0.25, 1.0));
+        //Only the templates sharing a key with the probe are candidates
+        assert_eq!((results[1].0, results[1].2), (2, 1.0));
+        assert!(results[1].1 > 0.25 && results[1].1 <= 0.5);
+
+        //A single impression is not probed, but still competes as a candidate
+        let mut single = templates.clone();
+        single[0].1.truncate(1);
+        let results = report(&single, ROWS + 3, COLS + 1, &[4]);
+        assert_eq!(results[0].2, 1.0);
+        assert!(report(&single[..1], ROWS + 3, COLS + 1, &[1]).is_empty());
+    }
+}
@@ -0,0 +1,835 @@
+//Reading and writing of KeePass KDBX 4 files, protected by a password (key files are not
+//supported). Layout of a file:
+//- outer header: signatures, version, then [id u8][size u32][data] fields, followed by its
+//  SHA-256 and its HMAC-SHA256
+//- the payload, encrypted with AES-256-CBC or ChaCha20 and cut in HMAC-SHA256 blocks
+//- once decrypted and gunzipped: the inner header (key of the stream protecting the
+//  passwords inside the XML) then the XML document itself
+//The keys come from the password: SHA-256 twice, transformed by the KDF (Argon2 or AES-KDF).
+use crate::crypto;
+use crate::importer::{Import, Note};
+use crate::models::Credential;
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use aes::Aes256;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20::ChaCha20;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256, Sha512};
+use std::io::{Read, Write};
+
+const SIGNATURE_1: u32 = 0x9AA2D903;
+const SIGNATURE_2: u32 = 0xB54BFB67;
+//4.0, read by KeePass 2.35 and KeePassXC 2.3 onwards
+const VERSION: u32 = 0x0004_0000;
+
+const CIPHER_AES256: [u8; 16] = [0x31, 0xC1, 0xF2, 0xE6, 0xBF, 0x71, 0x43, 0x50, 0xBE, 0x58, 0x05, 0x21, 0x6A, 0xFC, 0x5A, 0xFF];
+const CIPHER_CHACHA20: [u8; 16] = [0xD6, 0x03, 0x8A, 0x2B, 0x8B, 0x6F, 0x4C, 0xB5, 0xA5, 0x24, 0x33, 0x9A, 0x31, 0xDB, 0xB5, 0x9A];
+const KDF_ARGON2D: [u8; 16] = [0xEF, 0x63, 0x6D, 0xDF, 0x8C, 0x29, 0x44, 0x4B, 0x91, 0xF7, 0xA9, 0xA4, 0x03, 0xE3, 0x0A, 0x0C];
+const KDF_ARGON2ID: [u8; 16] = [0x9E, 0x29, 0x8B, 0x19, 0x56, 0xDB, 0x47, 0x73, 0xB2, 0x3D, 0xFC, 0x3E, 0xC6, 0xF0, 0xA1, 0xE6];
+//AES-KDF of KDBX 3.1 and its KDBX 4 UUID, the same transformation
+const KDF_AES: [u8; 16] = [0xC9, 0xD9, 0xF3, 0x9A, 0x62, 0x8A, 0x44, 0x60, 0xBF, 0x74, 0x0D, 0x08, 0xC1, 0x8A, 0x4F, 0xEA];
+const KDF_AES_KDBX4: [u8; 16] = [0x7C, 0x02, 0xBB, 0x82, 0x79, 0xA7, 0x4A, 0xC0, 0x92, 0x7D, 0x11, 0x4A, 0x00, 0x64, 0x82, 0x38];
+
+//Outer header fields
+const HEADER_END: u8 = 0;
+const HEADER_CIPHER: u8 = 2;
+const HEADER_COMPRESSION: u8 = 3;
+const HEADER_MASTER_SEED: u8 = 4;
+const HEADER_IV: u8 = 7;
+const HEADER_KDF: u8 = 11;
+//Inner header fields
+const INNER_END: u8 = 0;
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+const INNER_STREAM_CHACHA20: u32 = 3;
+
+//Argon2d cost of the exported files, the KeePassXC defaults
+const ARGON2_MEMORY_BYTES: u64 = 64 * 1024 * 1024;
+const ARGON2_ITERATIONS: u64 = 2;
+const ARGON2_PARALLELISM: u32 = 2;
+//Argon2 cost read from a file is untrusted, above this the file is refused instead of
+//exhausting the machine
+const MAX_ARGON2_MEMORY_BYTES: u64 = 1024 * 1024 * 1024;
+const MAX_ARGON2_ITERATIONS: u64 = 100;
+const MAX_ARGON2_PARALLELISM: u64 = 64;
+//Same for the AES-KDF rounds, KeePassXC asks for a few millions to take one second
+const MAX_AES_ROUNDS: u64 = 100_000_000;
+//Decompressed payload, well above the XML of a database holding thousands of entries
+const MAX_PAYLOAD_BYTES: u64 = 256 * 1024 * 1024;
+
+const BLOCK_SIZE: usize = 1024 * 1024;
+//Custom string of the entries holding the favourite flag, KeePass has no such field
+const FAVOURITE_FIELD: &str = "Bioguard-Favourite";
+//UUID written in the metadata when the recycle bin is disabled
+const NO_UUID: &str = "AAAAAAAAAAAAAAAAAAAAAA==";
+
+//Little endian reader over the file, every read fails cleanly on a truncated file
+struct Bytes<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(length).filter(|&end| end <= self.data.len()).ok_or("Truncated KeePass file")?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    //Header field or variant dictionary item
+    fn field(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let id = self.u8()?;
+        let length = self.u32()? as usize;
+        Ok((id, self.take(length)?))
+    }
+}
+
+//Typed values of the KDF parameters (a KeePass "variant dictionary")
+#[derive(Debug, Clone, PartialEq)]
+enum Variant {
+    U32(u32),
+    U64(u64),
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Variant {
+    fn kind(&self) -> u8 {
+        match self {
+            Variant::U32(_) => 0x04,
+            Variant::U64(_) => 0x05,
+            Variant::Bool(_) => 0x08,
+            Variant::I32(_) => 0x0C,
+            Variant::I64(_) => 0x0D,
+            Variant::Text(_) => 0x18,
+            Variant::Bytes(_) => 0x42,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Variant::U32(value) => value.to_le_bytes().to_vec(),
+            Variant::U64(value) => value.to_le_bytes().to_vec(),
+            Variant::Bool(value) => vec![*value as u8],
+            Variant::I32(value) => value.to_le_bytes().to_vec(),
+            Variant::I64(value) => value.to_le_bytes().to_vec(),
+            Variant::Text(value) => value.as_bytes().to_vec(),
+            Variant::Bytes(value) => value.clone(),
+        }
+    }
+}
+
+fn read_variants(data: &[u8]) -> Result<Vec<(String, Variant)>, String> {
+    let mut bytes = Bytes { data, position: 0 };
+    let version = u16::from_le_bytes(bytes.take(2)?.try_into().unwrap());
+    if version >> 8 != 1 {
+        return Err(format!("Unsupported KDF parameters version {:#x}", version));
+    }
+    let mut variants = Vec::new();
+    loop {
+        let kind = bytes.u8()?;
+        if kind == 0 {
+            return Ok(variants);
+        }
+        let name_length = bytes.u32()? as usize;
+        let name = String::from_utf8_lossy(bytes.take(name_length)?).to_string();
+        let value_length = bytes.u32()? as usize;
+        let value = bytes.take(value_length)?;
+        let fixed = |length: usize| -> Result<&[u8], String> {
+            if value.len() == length { Ok(value) } else { Err(format!("Malformed KDF parameter {}", name)) }
+        };
+        let variant = match kind {
+            0x04 => Variant::U32(u32::from_le_bytes(fixed(4)?.try_into().unwrap())),
+            0x05 => Variant::U64(u64::from_le_bytes(fixed(8)?.try_into().unwrap())),
+            0x08 => Variant::Bool(fixed(1)?[0] != 0),
+            0x0C => Variant::I32(i32::from_le_bytes(fixed(4)?.try_into().unwrap())),
+            0x0D => Variant::I64(i64::from_le_bytes(fixed(8)?.try_into().unwrap())),
+            0x18 => Variant::Text(String::from_utf8_lossy(value).to_string()),
+            0x42 => Variant::Bytes(value.to_vec()),
+            _ => return Err(format!("Unknown KDF parameter type {:#x}", kind)),
+        };
+        variants.push((name, variant));
+    }
+}
+
+fn write_variants(variants: &[(&str, Variant)]) -> Vec<u8> {
+    let mut data = 0x0100u16.to_le_bytes().to_vec();
+    for (name, variant) in variants {
+        let value = variant.to_bytes();
+        data.push(variant.kind());
+        data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        data.extend_from_slice(&value);
+    }
+    data.push(0);
+    data
+}
+
+fn variant<'a>(variants: &'a [(String, Variant)], name: &str) -> Result<&'a Variant, String> {
+    variants
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, variant)| variant)
+        .ok_or(format!("Missing KDF parameter {}", name))
+}
+
+fn variant_u64(variants: &[(String, Variant)], name: &str) -> Result<u64, String> {
+    match variant(variants, name)? {
+        Variant::U64(value) => Ok(*value),
+        Variant::U32(value) => Ok(*value as u64),
+        _ => Err(format!("Malformed KDF parameter {}", name)),
+    }
+}
+
+fn variant_bytes<'a>(variants: &'a [(String, Variant)], name: &str) -> Result<&'a [u8], String> {
+    match variant(variants, name)? {
+        Variant::Bytes(value) => Ok(value),
+        _ => Err(format!("Malformed KDF parameter {}", name)),
+    }
+}
+
+//Password only composite key
+fn composite_key(password: &str) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(password.as_bytes())).into()
+}
+
+fn transform_key(composite: &[u8; 32], kdf: &[(String, Variant)]) -> Result<[u8; 32], String> {
+    let uuid = variant_bytes(kdf, "$UUID")?;
+    let mut transformed = [0u8; 32];
+    if uuid == KDF_ARGON2D || uuid == KDF_ARGON2ID {
+        if kdf.iter().any(|(name, _)| name == "K" || name == "A") {
+            return Err("Argon2 secret keys and associated data are not supported".to_string());
+        }
+        let algorithm = if uuid == KDF_ARGON2D { Algorithm::Argon2d } else { Algorithm::Argon2id };
+        let version = match variant_u64(kdf, "V")? {
+            0x10 => Version::V0x10,
+            0x13 => Version::V0x13,
+            other => return Err(format!("Unsupported Argon2 version {:#x}", other)),
+        };
+        let (memory, iterations, parallelism) = (variant_u64(kdf, "M")?, variant_u64(kdf, "I")?, variant_u64(kdf, "P")?);
+        if memory > MAX_ARGON2_MEMORY_BYTES || iterations > MAX_ARGON2_ITERATIONS || parallelism > MAX_ARGON2_PARALLELISM {
+            return Err("The KeePass file asks for too much memory or time to derive its key".to_string());
+        }
+        let params = Params::new((memory / 1024) as u32, iterations as u32, parallelism as u32, Some(32)).map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+        Argon2::new(algorithm, version, params)
+            .hash_password_into(composite, variant_bytes(kdf, "S")?, &mut transformed)
+            .map_err(|e| format!("Argon2 key derivation failed: {}", e))?;
+        Ok(transformed)
+    } else if uuid == KDF_AES || uuid == KDF_AES_KDBX4 {
+        let seed = variant_bytes(kdf, "S")?;
+        let rounds = variant_u64(kdf, "R")?;
+        if rounds > MAX_AES_ROUNDS {
+            return Err("The KeePass file asks for too much memory or time to derive its key".to_string());
+        }
+        let cipher = Aes256::new_from_slice(seed).map_err(|_| "Malformed AES-KDF seed")?;
+        let mut key = *composite;
+        for half in key.chunks_mut(16) {
+            let block = aes::Block::from_mut_slice(half);
+            for _ in 0..rounds {
+                cipher.encrypt_block(block);
+            }
+        }
+        transformed.copy_from_slice(&Sha256::digest(key));
+        Ok(transformed)
+    } else {
+        Err("Unsupported key derivation function".to_string())
+    }
+}
+
+//Key of the HMAC of a block, the header uses the index u64::MAX
+fn hmac_key(hmac_base: &[u8], index: u64) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update(index.to_le_bytes());
+    hasher.update(hmac_base);
+    hasher.finalize().to_vec()
+}
+
+fn block_hmac(hmac_base: &[u8], index: u64, data: &[u8]) -> Vec<u8> {
+    let mut message = index.to_le_bytes().to_vec();
+    message.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    message.extend_from_slice(data);
+    crypto::hmac::<Sha256>(&hmac_key(hmac_base, index), &message)
+}
+
+//Unlike the blocks, only the header bytes are authenticated
+fn header_hmac(hmac_base: &[u8], header: &[u8]) -> Vec<u8> {
+    crypto::hmac::<Sha256>(&hmac_key(hmac_base, u64::MAX), header)
+}
+
+//Keys derived from the master seed and the transformed key: (encryption key, HMAC base)
+fn payload_keys(master_seed: &[u8], transformed: &[u8; 32]) -> ([u8; 32], Vec<u8>) {
+    let mut encryption = Sha256::new();
+    encryption.update(master_seed);
+    encryption.update(transformed);
+    let mut hmac = Sha512::new();
+    hmac.update(master_seed);
+    hmac.update(transformed);
+    hmac.update([1u8]);
+    (encryption.finalize().into(), hmac.finalize().to_vec())
+}
+
+//Stream protecting the values marked Protected in the XML, in document order
+fn inner_stream(key: &[u8]) -> ChaCha20 {
+    let hash = Sha512::digest(key);
+    ChaCha20::new_from_slices(&hash[..32], &hash[32..44]).unwrap()
+}
+
+fn decrypt_payload(cipher: &[u8], key: &[u8; 32], iv: &[u8], data: Vec<u8>) -> Result<Vec<u8>, String> {
+    if cipher == CIPHER_CHACHA20 {
+        let mut data = data;
+        ChaCha20::new_from_slices(key, iv).map_err(|_| "Malformed ChaCha20 IV")?.apply_keystream(&mut data);
+        Ok(data)
+    } else if cipher == CIPHER_AES256 {
+        cbc::Decryptor::<Aes256>::new_from_slices(key, iv)
+            .map_err(|_| "Malformed AES IV")?
+            .decrypt_padded_vec_mut::<Pkcs7>(&data)
+            .map_err(|_| "Failed to decrypt the KeePass file".to_string())
+    } else {
+        Err("Unsupported cipher, only AES-256 and ChaCha20 are read".to_string())
+    }
+}
+
+//Credential being read from the XML
+#[derive(Default)]
+struct XmlEntry {
+    title: String,
+    username: String,
+    password: String,
+    url: String,
+    notes: String,
+    otp: String,
+    tags: Vec<String>,
+    favourite: bool,
+}
+
+//Walks the XML once: protected values are decrypted in document order (history included,
+//to keep the stream in step) and only the current version of the entries is kept
+struct XmlReader {
+    stream: ChaCha20,
+    path: Vec<String>,
+    //Name and UUID of the groups containing the current element, the root group first
+    groups: Vec<(String, String)>,
+    recycle_bin: String,
+    text: String,
+    key: String,
+    protected: bool,
+    entry: Option<XmlEntry>,
+    entries: Vec<(XmlEntry, bool)>,
+}
+
+impl XmlReader {
+    fn parent(&self) -> &str {
+        self.path.iter().rev().nth(1).map(|name| name.as_str()).unwrap_or("")
+    }
+
+    fn in_history(&self) -> bool {
+        self.path.iter().any(|name| name == "History")
+    }
+
+    fn start(&mut self, element: &BytesStart) -> Result<(), String> {
+        let name = String::from_utf8_lossy(element.name().as_ref()).to_string();
+        self.path.push(name.clone());
+        self.text.clear();
+        match name.as_str() {
+            "Group" => self.groups.push((String::new(), String::new())),
+            "Entry" if !self.in_history() => self.entry = Some(XmlEntry::default()),
+            "Value" => {
+                self.protected = element
+                    .try_get_attribute("Protected")
+                    .map_err(|e| format!("Malformed KeePass XML: {}", e))?
+                    .map(|attribute| attribute.value.eq_ignore_ascii_case(b"true"))
+                    .unwrap_or(false);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        let name = self.path.last().cloned().unwrap_or_default();
+        let parent = self.parent().to_string();
+        let text = std::mem::take(&mut self.text);
+        match (parent.as_str(), name.as_str()) {
+            ("Meta", "RecycleBinUUID") if text.trim() != NO_UUID => self.recycle_bin = text.trim().to_string(),
+            ("Group", "Name") => {
+                if let Some(group) = self.groups.last_mut() {
+                    group.0 = text;
+                }
+            }
+            ("Group", "UUID") => {
+                if let Some(group) = self.groups.last_mut() {
+                    group.1 = text.trim().to_string();
+                }
+            }
+            ("String", "Key") => self.key = text,
+            ("String", "Value") => {
+                let value = if self.protected {
+                    let mut bytes = BASE64.decode(text.trim()).map_err(|_| "Malformed protected value in the KeePass XML")?;
+                    self.stream.apply_keystream(&mut bytes);
+                    String::from_utf8_lossy(&bytes).to_string()
+                } else {
+                    text
+                };
+                let in_history = self.in_history();
+                if let (Some(entry), false) = (self.entry.as_mut(), in_history) {
+                    match self.key.as_str() {
+                        "Title" => entry.title = value,
+                        "UserName" => entry.username = value,
+                        "Password" => entry.password = value,
+                        "URL" => entry.url = value,
+                        "Notes" => entry.notes = value,
+                        //KeePassXC stores an otpauth URI, KeePass 2.47+ a base32 secret
+                        "otp" | "TimeOtp-Secret-Base32" => entry.otp = value,
+                        FAVOURITE_FIELD => entry.favourite = value == "true",
+                        _ => {}
+                    }
+                }
+            }
+            ("Entry", "Tags") if !self.in_history() => {
+                if let Some(entry) = self.entry.as_mut() {
+                    entry.tags.extend(text.split([';', ',']).map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()));
+                }
+            }
+            (_, "Entry") if !self.in_history() => {
+                if let Some(mut entry) = self.entry.take() {
+                    let deleted = !self.recycle_bin.is_empty() && self.groups.iter().any(|(_, uuid)| *uuid == self.recycle_bin);
+                    //The groups below the root become tags, like folders
+                    let folders: Vec<&str> = self.groups.iter().skip(1).map(|(name, _)| name.as_str()).collect();
+                    if !folders.is_empty() {
+                        entry.tags.insert(0, folders.join("/"));
+                    }
+                    self.entries.push((entry, deleted));
+                }
+            }
+            (_, "Group") => {
+                self.groups.pop();
+            }
+            _ => {}
+        }
+        self.path.pop();
+        Ok(())
+    }
+}
+
+fn read_xml(xml: &str, stream_key: &[u8]) -> Result<Vec<(XmlEntry, bool)>, String> {
+    let mut state = XmlReader {
+        stream: inner_stream(stream_key),
+        path: Vec::new(),
+        groups: Vec::new(),
+        recycle_bin: String::new(),
+        text: String::new(),
+        key: String::new(),
+        protected: false,
+        entry: None,
+        entries: Vec::new(),
+    };
+    let mut reader = Reader::from_str(xml);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(element)) => state.start(&element)?,
+            Ok(Event::Empty(element)) => {
+                state.start(&element)?;
+                state.end()?;
+            }
+            Ok(Event::End(_)) => state.end()?,
+            Ok(Event::Text(text)) => {
+                state.text.push_str(&text.unescape().map_err(|e| format!("Malformed KeePass XML: {}", e))?);
+            }
+            Ok(Event::CData(data)) => state.text.push_str(&String::from_utf8_lossy(&data)),
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err(format!("Malformed KeePass XML at byte {}: {}", reader.buffer_position(), e)),
+        }
+    }
+    Ok(state.entries)
+}
+
+//Stops at `limit` bytes, a small file can inflate to gigabytes
+fn gunzip(data: &[u8], limit: u64) -> Result<Vec<u8>, String> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(data)
+        .take(limit + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| format!("Failed to decompress the KeePass file: {}", e))?;
+    if decompressed.len() as u64 > limit {
+        return Err("The KeePass file is too large once decompressed".to_string());
+    }
+    Ok(decompressed)
+}
+
+//Reads a KDBX 4 file into an import preview, entries are numbered from 1 in file order
+pub fn import(data: &[u8], password: &str) -> Result<Import, String> {
+    let mut bytes = Bytes { data, position: 0 };
+    if bytes.u32()? != SIGNATURE_1 || bytes.u32()? != SIGNATURE_2 {
+        return Err("Not a KeePass database".to_string());
+    }
+    if bytes.u32()? >> 16 != 4 {
+        return Err("Only KDBX 4 files are read, save the database with KeePass 2.35 or KeePassXC 2.3 or later".to_string());
+    }
+
+    let mut cipher = None;
+    let mut compressed = false;
+    let mut master_seed = None;
+    let mut iv = None;
+    let mut kdf = None;
+    loop {
+        let (id, value) = bytes.field()?;
+        match id {
+            HEADER_END => break,
+            HEADER_CIPHER => cipher = Some(value),
+            HEADER_COMPRESSION => compressed = value.first().copied().unwrap_or(0) == 1,
+            HEADER_MASTER_SEED => master_seed = Some(value),
+            HEADER_IV => iv = Some(value),
+            HEADER_KDF => kdf = Some(read_variants(value)?),
+            _ => {}
+        }
+    }
+    let header = &data[..bytes.position];
+    let (cipher, master_seed, iv, kdf) = match (cipher, master_seed, iv, kdf) {
+        (Some(cipher), Some(master_seed), Some(iv), Some(kdf)) => (cipher, master_seed, iv, kdf),
+        _ => return Err("Incomplete KeePass header".to_string()),
+    };
+
+    if Sha256::digest(header).as_slice() != bytes.take(32)? {
+        return Err("Damaged KeePass header".to_string());
+    }
+    let transformed = transform_key(&composite_key(password), &kdf)?;
+    let (key, hmac_base) = payload_keys(master_seed, &transformed);
+    if header_hmac(&hmac_base, header).as_slice() != bytes.take(32)? {
+        return Err("Wrong password, or the database also needs a key file".to_string());
+    }
+
+    let mut encrypted = Vec::new();
+    for index in 0u64.. {
+        let mac = bytes.take(32)?;
+        let length = bytes.u32()? as usize;
+        let block = bytes.take(length)?;
+        if block_hmac(&hmac_base, index, block).as_slice() != mac {
+            return Err(format!("Damaged KeePass file, block {} does not match its HMAC", index));
+        }
+        if length == 0 {
+            break;
+        }
+        encrypted.extend_from_slice(block);
+    }
+
+    let mut payload = decrypt_payload(cipher, &key, iv, encrypted)?;
+    if compressed {
+        payload = gunzip(&payload, MAX_PAYLOAD_BYTES)?;
+    }
+
+    let mut inner = Bytes { data: &payload, position: 0 };
+    let mut stream_id = None;
+    let mut stream_key = None;
+    loop {
+        let (id, value) = inner.field()?;
+        match id {
+            INNER_END => break,
+            INNER_STREAM_ID if value.len() == 4 => stream_id = Some(u32::from_le_bytes(value.try_into().unwrap())),
+            INNER_STREAM_KEY => stream_key = Some(value),
+            _ => {}
+        }
+    }
+    let stream_key = match (stream_id, stream_key) {
+        (Some(INNER_STREAM_CHACHA20), Some(stream_key)) => stream_key,
+        _ => return Err("Unsupported protection of the KeePass passwords, only ChaCha20 is read".to_string()),
+    };
+    let xml = String::from_utf8_lossy(&payload[inner.position..]);
+
+    let mut import = Import::new("KeePass");
+    for (index, (entry, deleted)) in read_xml(&xml, stream_key)?.into_iter().enumerate() {
+        let line = index + 1;
+        if deleted {
+            import.skipped.push(Note { line, message: format!("{}: in the recycle bin", entry.title) });
+            continue;
+        }
+        let credential = Credential {
+            site: entry.title.trim().to_string(),
+            site_username: entry.username,
+            site_password: entry.password,
+            url: entry.url.trim().to_string(),
+            notes: entry.notes,
+            tags: entry.tags.join(", "),
+            favourite: entry.favourite,
+            otp: entry.otp.trim().to_string(),
+            ..Credential::default()
+        };
+        import.push(line, Ok(credential));
+    }
+    Ok(import)
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; length];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn write_string(xml: &mut String, key: &str, value: &str, stream: Option<&mut ChaCha20>) {
+    xml.push_str(&format!("\t\t\t\t<String>\n\t\t\t\t\t<Key>{}</Key>\n", escape(key)));
+    match stream {
+        Some(stream) => {
+            let mut bytes = value.as_bytes().to_vec();
+            stream.apply_keystream(&mut bytes);
+            xml.push_str(&format!("\t\t\t\t\t<Value Protected=\"True\">{}</Value>\n", BASE64.encode(bytes)));
+        }
+        None => xml.push_str(&format!("\t\t\t\t\t<Value>{}</Value>\n", escape(value))),
+    }
+    xml.push_str("\t\t\t\t</String>\n");
+}
+
+fn write_xml(credentials: &[Credential], stream_key: &[u8]) -> String {
+    let mut stream = inner_stream(stream_key);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n<KeePassFile>\n");
+    xml.push_str("\t<Meta>\n\t\t<Generator>Bioguard</Generator>\n\t\t<DatabaseName>Bioguard</DatabaseName>\n\t</Meta>\n");
+    xml.push_str("\t<Root>\n\t\t<Group>\n");
+    xml.push_str(&format!("\t\t\t<UUID>{}</UUID>\n\t\t\t<Name>Bioguard</Name>\n", BASE64.encode(random_bytes(16))));
+    for credential in credentials {
+        xml.push_str(&format!("\t\t\t<Entry>\n\t\t\t\t<UUID>{}</UUID>\n", BASE64.encode(random_bytes(16))));
+        let tags: Vec<&str> = credential.tags.split(',').map(|tag| tag.trim()).filter(|tag| !tag.is_empty()).collect();
+        xml.push_str(&format!("\t\t\t\t<Tags>{}</Tags>\n", escape(&tags.join(";"))));
+        write_string(&mut xml, "Title", &credential.site, None);
+        write_string(&mut xml, "UserName", &credential.site_username, None);
+        write_string(&mut xml, "Password", &credential.site_password, Some(&mut stream));
+        write_string(&mut xml, "URL", &credential.url, None);
+        write_string(&mut xml, "Notes", &credential.notes, None);
+        if !credential.otp.is_empty() {
+            write_string(&mut xml, "otp", &credential.otp, Some(&mut stream));
+        }
+        if credential.favourite {
+            write_string(&mut xml, FAVOURITE_FIELD, "true", None);
+        }
+        xml.push_str("\t\t\t</Entry>\n");
+    }
+    xml.push_str("\t\t</Group>\n\t</Root>\n</KeePassFile>\n");
+    xml
+}
+
+fn push_field(data: &mut Vec<u8>, id: u8, value: &[u8]) {
+    data.push(id);
+    data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    data.extend_from_slice(value);
+}
+
+//Writes the credentials as a KDBX 4 file: ChaCha20, Argon2d and gzip, as KeePassXC does
+pub fn export(credentials: &[Credential], password: &str) -> Result<Vec<u8>, String> {
+    let kdf = write_variants(&[
+        ("$UUID", Variant::Bytes(KDF_ARGON2D.to_vec())),
+        ("S", Variant::Bytes(random_bytes(32))),
+        ("P", Variant::U32(ARGON2_PARALLELISM)),
+        ("M", Variant::U64(ARGON2_MEMORY_BYTES)),
+        ("I", Variant::U64(ARGON2_ITERATIONS)),
+        ("V", Variant::U32(0x13)),
+    ]);
+    write(credentials, password, &kdf)
+}
+
+fn write(credentials: &[Credential], password: &str, kdf: &[u8]) -> Result<Vec<u8>, String> {
+    let master_seed = random_bytes(32);
+    let iv = random_bytes(12);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&SIGNATURE_1.to_le_bytes());
+    header.extend_from_slice(&SIGNATURE_2.to_le_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    push_field(&mut header, HEADER_CIPHER, &CIPHER_CHACHA20);
+    push_field(&mut header, HEADER_COMPRESSION, &1u32.to_le_bytes());
+    push_field(&mut header, HEADER_MASTER_SEED, &master_seed);
+    push_field(&mut header, HEADER_IV, &iv);
+    push_field(&mut header, HEADER_KDF, kdf);
+    push_field(&mut header, HEADER_END, b"\r\n\r\n");
+
+    let kdf = read_variants(kdf)?;
+    let transformed = transform_key(&composite_key(password), &kdf)?;
+    let (key, hmac_base) = payload_keys(&master_seed, &transformed);
+
+    let stream_key = random_bytes(64);
+    let mut payload = Vec::new();
+    push_field(&mut payload, INNER_STREAM_ID, &INNER_STREAM_CHACHA20.to_le_bytes());
+    push_field(&mut payload, INNER_STREAM_KEY, &stream_key);
+    push_field(&mut payload, INNER_END, &[]);
+    payload.extend_from_slice(write_xml(credentials, &stream_key).as_bytes());
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&payload).map_err(|e| e.to_string())?;
+    let mut encrypted = encoder.finish().map_err(|e| e.to_string())?;
+    ChaCha20::new_from_slices(&key, &iv).unwrap().apply_keystream(&mut encrypted);
+
+    let mut file = header.clone();
+    file.extend_from_slice(&Sha256::digest(&header));
+    file.extend_from_slice(&header_hmac(&hmac_base, &header));
+    for (index, block) in encrypted.chunks(BLOCK_SIZE).chain(std::iter::once(&[][..])).enumerate() {
+        file.extend_from_slice(&block_hmac(&hmac_base, index as u64, block));
+        file.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        file.extend_from_slice(block);
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Cheap KDF settings, the exported cost makes a debug test run for seconds
+    fn argon2id_kdf() -> Vec<u8> {
+        write_variants(&[
+            ("$UUID", Variant::Bytes(KDF_ARGON2ID.to_vec())),
+            ("S", Variant::Bytes(random_bytes(32))),
+            ("P", Variant::U32(1)),
+            ("M", Variant::U64(64 * 1024)),
+            ("I", Variant::U64(1)),
+            ("V", Variant::U32(0x13)),
+        ])
+    }
+
+    fn aes_kdf() -> Vec<u8> {
+        write_variants(&[("$UUID", Variant::Bytes(KDF_AES_KDBX4.to_vec())), ("S", Variant::Bytes(random_bytes(32))), ("R", Variant::U64(100))])
+    }
+
+    fn credentials() -> Vec<Credential> {
+        vec![
+            Credential {
+                site: "GitLab".to_string(),
+                site_username: "alice".to_string(),
+                site_password: "p<a>ss & \"word\"".to_string(),
+                url: "https://gitlab.com".to_string(),
+                notes: "first line\nsecond line".to_string(),
+                tags: "work, dev".to_string(),
+                favourite: true,
+                otp: "otpauth://totp/GitLab%3Aalice?secret=JBSWY3DPEHPK3PXP&algorithm=SHA1&digits=6&period=30".to_string(),
+                ..Credential::default()
+            },
+            Credential { site: "Router".to_string(), site_username: "admin".to_string(), site_password: "hunter2".to_string(), ..Credential::default() },
+        ]
+    }
+
+    #[test]
+    fn export_import_roundtrip() {
+        for kdf in [argon2id_kdf(), aes_kdf()] {
+            let file = write(&credentials(), "correct horse battery", &kdf).unwrap();
+            let import = import(&file, "correct horse battery").unwrap();
+            assert!(import.skipped.is_empty());
+            let imported: Vec<Credential> = import.entries.into_iter().map(|entry| entry.credential).collect();
+            assert_eq!(imported, credentials());
+        }
+    }
+
+    #[test]
+    fn wrong_password_is_refused() {
+        let file = write(&credentials(), "correct horse battery", &argon2id_kdf()).unwrap();
+        assert_eq!(import(&file, "incorrect horse battery").unwrap_err(), "Wrong password, or the database also needs a key file");
+    }
+
+    #[test]
+    fn tampered_file_is_refused() {
+        let file = write(&credentials(), "correct horse battery", &argon2id_kdf()).unwrap();
+        //Last byte of the payload, before the HMAC and length of the closing empty block
+        let mut tampered = file.clone();
+        let last = tampered.len() - 37;
+        tampered[last] ^= 1;
+        assert_eq!(import(&tampered, "correct horse battery").unwrap_err(), "Damaged KeePass file, block 0 does not match its HMAC");
+
+        let mut tampered = file;
+        tampered[12] ^= 1;
+        assert!(import(&tampered, "correct horse battery").is_err());
+    }
+
+    #[test]
+    fn excessive_argon2_cost_is_refused() {
+        for (memory, iterations, parallelism) in [(u64::MAX, 2, 2), (64 * 1024 * 1024, MAX_ARGON2_ITERATIONS + 1, 2), (64 * 1024 * 1024, 2, u32::MAX)] {
+            let kdf = write_variants(&[
+                ("$UUID", Variant::Bytes(KDF_ARGON2D.to_vec())),
+                ("S", Variant::Bytes(random_bytes(32))),
+                ("P", Variant::U32(parallelism)),
+                ("M", Variant::U64(memory)),
+                ("I", Variant::U64(iterations)),
+                ("V", Variant::U32(0x13)),
+            ]);
+            let kdf = read_variants(&kdf).unwrap();
+            assert_eq!(transform_key(&composite_key("password"), &kdf).unwrap_err(), "The KeePass file asks for too much memory or time to derive its key");
+        }
+    }
+
+    //Not written by this module: made by a separate implementation of the KDBX 4.1 format with
+    //AES-KDF, AES-256-CBC, payload blocks of 100 bytes, nested groups, entry history, a
+    //recycle bin and an inner header attachment. Password "hunter2".
+    const FOREIGN_KDBX: &[u8] = include_bytes!("../tests/fixtures/keepass_aes_kdf.kdbx");
+
+    #[test]
+    fn foreign_file_is_read() {
+        let import = import(FOREIGN_KDBX, "hunter2").unwrap();
+        let entries: Vec<(usize, &Credential)> = import.entries.iter().map(|entry| (entry.line, &entry.credential)).collect();
+        assert_eq!(entries.len(), 3);
+
+        //The history of the entry is skipped but its protected values keep the stream in step
+        let (line, top) = entries[0];
+        assert_eq!(line, 1);
+        assert_eq!((top.site.as_str(), top.site_username.as_str(), top.site_password.as_str(), top.tags.as_str()), ("Top & Co", "u1", "pw-top", "a, b"));
+
+        //No title: the site comes from the URL, the groups become a tag
+        let (line, server) = entries[1];
+        assert_eq!(line, 2);
+        assert_eq!(server.site, "db.example.com");
+        assert_eq!(server.site_password, "  sp ace  ");
+        assert_eq!(server.notes, "multi\nline");
+        assert_eq!(server.tags, "Work/Servers");
+        assert!(server.otp.starts_with("otpauth://totp/") && server.otp.contains("secret=JBSWY3DPEHPK3PXP"));
+
+        let (line, last) = entries[2];
+        assert_eq!(line, 5);
+        assert_eq!(last.site_password, "pw-last");
+
+        let skipped: Vec<(usize, &str)> = import.skipped.iter().map(|note| (note.line, note.message.as_str())).collect();
+        assert_eq!(skipped, vec![(3, "no password"), (4, "trash: in the recycle bin")]);
+
+        assert_eq!(super::import(FOREIGN_KDBX, "hunter3").unwrap_err(), "Wrong password, or the database also needs a key file");
+    }
+
+    #[test]
+    fn excessive_aes_rounds_are_refused() {
+        for rounds in [MAX_AES_ROUNDS + 1, u64::MAX] {
+            let kdf = write_variants(&[("$UUID", Variant::Bytes(KDF_AES.to_vec())), ("S", Variant::Bytes(random_bytes(32))), ("R", Variant::U64(rounds))]);
+            let kdf = read_variants(&kdf).unwrap();
+            assert_eq!(transform_key(&composite_key("password"), &kdf).unwrap_err(), "The KeePass file asks for too much memory or time to derive its key");
+        }
+    }
+
+    #[test]
+    fn inflated_payload_is_limited() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&vec![0u8; 4096]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(gunzip(&compressed, 4096).unwrap().len(), 4096);
+        assert_eq!(gunzip(&compressed, 4095).unwrap_err(), "The KeePass file is too large once decompressed");
+    }
+}
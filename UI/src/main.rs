@@ -4,6 +4,21 @@ extern crate dotenv;
 
 mod extractor;
 use crate::extractor::*;
+mod crypto;
+mod index;
+mod protection;
+mod vault;
+mod fuzzy;
+mod recovery;
+mod totp;
+mod generator;
+mod strength;
+mod breach;
+mod health;
+mod importer;
+mod kdbx;
+mod backup;
+mod exporter;
 
 use std::io::Write;
 use database::*;
@@ -17,34 +32,103 @@ use std::env;
 use std::fs::File;
 use std::future::IntoFuture;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use image::{load_from_memory, ImageError, DynamicImage, ImageFormat};
 
 mod models;
 mod database;
 
-use crate::models::{User, Credential};
-use crate::database::{establish_connection, create_tables, save_user, get_user, get_credentials};
+use crate::models::{User, Credential, Finger, EnrolledFinger, PasswordHistory, HealthIssue, ImportRow, Item, ItemKind, ItemContent, ItemField, GeneratorMode, GeneratorSettings, ExportLayout};
+use crate::vault::VaultKey;
+use crate::database::{establish_connection, connect, run_migrations, schema_version, save_user, get_user, get_credentials, save_template, get_templates, get_all_templates, delete_templates, get_unindexed_templates, get_template_with_user, save_template_keys, has_template_keys, clear_template_index, get_template_votes, update_template, encrypt_legacy_credentials, save_key_release, get_key_release, count_key_releases, get_vault_recovery, save_master_password, save_recovery_key};
 use std::process::{ExitStatus};
 use tokio::process::Command;
 use std::io;
 
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use druid::widget::{Button, Flex, Label, Padding, TextBox, Scroll, List, SizedBox};
-use druid::{AppDelegate as OtherAppDelegate, AppLauncher, Data, Handled, Lens, Selector, WidgetExt, WindowDesc, Widget, Command as DruidCommand, Target, Color};
+use druid::widget::{Button, Checkbox, Controller, CrossAxisAlignment, Flex, Label, LineBreaking, Padding, TextBox, Scroll, List, SizedBox, Slider};
+use druid::{AppDelegate as OtherAppDelegate, AppLauncher, Application, Env, Event, EventCtx, UpdateCtx, TimerToken, Data, Handled, Lens, Selector, WidgetExt, WindowDesc, Widget, Command as DruidCommand, Target, Color};
 
 #[derive(Debug, Clone, Data, Lens)]
 struct AppState {
     view: ViewSelector,
     name: String,
     username: String,
+    //Why the last fingerprint login failed, shown in the login view
+    login_message: String,
+    //Why the last registration failed, shown in the register view
+    register_message: String,
     fingerprint_path: String,
     site: String,
     site_username: String,
     site_password: String,
+    url: String,
+    notes: String,
+    tags: String,
+    favourite: bool,
+    otp: String,
+    generator: GeneratorSettings,
+    show_generator: bool,
+    //Strength summary of the password of the form and the times it was seen in the breach
+    //dataset, both computed when it changes
+    password_strength: String,
+    password_breaches: Option<u64>,
     credentials: Arc<Vec<Credential>>,
+    //Id of the credential loaded in the form, None when adding a new one
+    editing: Option<i32>,
+    //Id of the row picked in the table for deletion
+    selected: Option<i32>,
+    //Previous passwords of the credential being edited
+    history: Arc<Vec<PasswordHistory>>,
+    //Kind of items shown in the credentials view, None for the site logins
+    category: Option<ItemKind>,
+    items: Arc<Vec<Item>>,
+    item_title: String,
+    item_fields: Arc<Vec<ItemField>>,
+    editing_item: Option<i64>,
+    //Outcome of the last change made in the credentials view
+    status_message: String,
+    finger: Finger,
+    fingers: Arc<Vec<EnrolledFinger>>,
+    //Outcome of the last enrolment or removal in the Fingers view
+    fingers_message: String,
+    //Only set after a successful fingerprint verification or a fallback unlock
+    vault_key: Option<Arc<VaultKey>>,
+    master_password: String,
+    master_password_confirm: String,
+    recovery_key: String,
+    security_message: String,
+    //Outcome of the last check against the breach dataset
+    breach_message: String,
+    health: Arc<Vec<HealthIssue>>,
+    health_message: String,
+    import_path: String,
+    import_rows: Arc<Vec<ImportRow>>,
+    import_message: String,
+    //Secure notes of the previewed export that are not in the vault yet
+    import_items: Arc<Vec<Item>>,
+    //Password of the KeePass file read or written
+    kdbx_password: String,
+    //Typed again before writing a KeePass file, the import does not need it
+    kdbx_password_confirm: String,
+    backup_path: String,
+    backup_password: String,
+    backup_password_confirm: String,
+    //Recovery key typed to protect or open a backup instead of a password
+    backup_recovery_key: String,
+    //Restore deletes the credentials and items of the vault first instead of merging
+    backup_replace: bool,
+    backup_message: String,
+    export_layout: ExportLayout,
+    export_path: String,
+    //Unix time of the fingerprint verification allowing a plaintext export, None until verified
+    export_verified_at: Option<u64>,
+    //Explicit confirmation that the file is written unencrypted
+    export_confirmed: bool,
+    export_message: String,
 }
 
 impl AppState {
@@ -52,14 +136,132 @@ impl AppState {
         Self {
             name: "Bioguard".into(),
             username: "".into(),
+            login_message: "".into(),
+            register_message: "".into(),
             fingerprint_path: "".into(),
             site: "".into(),
             site_username: "".into(),
             site_password: "".into(),
+            url: "".into(),
+            notes: "".into(),
+            tags: "".into(),
+            favourite: false,
+            otp: "".into(),
+            generator: GeneratorSettings::default(),
+            show_generator: false,
+            password_strength: "".into(),
+            password_breaches: None,
             credentials: Arc::new(Vec::new()),
+            editing: None,
+            selected: None,
+            history: Arc::new(Vec::new()),
+            category: None,
+            items: Arc::new(Vec::new()),
+            item_title: "".into(),
+            item_fields: Arc::new(Vec::new()),
+            editing_item: None,
+            status_message: "".into(),
+            finger: Finger::RightIndex,
+            fingers: Arc::new(Vec::new()),
+            vault_key: None,
+            master_password: "".into(),
+            master_password_confirm: "".into(),
+            recovery_key: "".into(),
+            fingers_message: "".into(),
+            security_message: "".into(),
+            breach_message: "".into(),
+            health: Arc::new(Vec::new()),
+            health_message: "".into(),
+            import_path: "".into(),
+            import_rows: Arc::new(Vec::new()),
+            import_message: "".into(),
+            import_items: Arc::new(Vec::new()),
+            kdbx_password: "".into(),
+            kdbx_password_confirm: "".into(),
+            backup_path: "".into(),
+            backup_password: "".into(),
+            backup_password_confirm: "".into(),
+            backup_recovery_key: "".into(),
+            backup_replace: false,
+            backup_message: "".into(),
+            export_layout: ExportLayout::BitwardenJson,
+            export_path: "".into(),
+            export_verified_at: None,
+            export_confirmed: false,
+            export_message: "".into(),
             view: ViewSelector::Login,
         }
     }
+
+    //Credential described by the form, id is 0 when adding a new one
+    fn form_credential(&self) -> Credential {
+        Credential {
+            id: self.editing.unwrap_or(0),
+            username: self.username.clone(),
+            site: self.site.clone(),
+            site_username: self.site_username.clone(),
+            site_password: self.site_password.clone(),
+            url: self.url.clone(),
+            notes: self.notes.clone(),
+            tags: self.tags.clone(),
+            favourite: self.favourite,
+            otp: self.otp.trim().to_string(),
+            otp_code: "".into(),
+            breaches: 0,
+            strength: 0,
+            created_at: "".into(),
+            modified_at: "".into(),
+            last_used_at: "".into(),
+            password_changed_at: "".into(),
+        }
+    }
+
+    fn load_form(&mut self, cred: Credential) {
+        self.editing = Some(cred.id);
+        self.site = cred.site;
+        self.site_username = cred.site_username;
+        self.site_password = cred.site_password;
+        self.url = cred.url;
+        self.notes = cred.notes;
+        self.tags = cred.tags;
+        self.favourite = cred.favourite;
+        self.otp = cred.otp;
+    }
+
+    //Empty item form for the selected kind
+    fn clear_item_form(&mut self) {
+        self.editing_item = None;
+        self.item_title = "".to_string();
+        self.item_fields = Arc::new(self.category.map(|kind| ItemContent::empty(kind).fields()).unwrap_or_default());
+    }
+
+    fn clear_form(&mut self) {
+        self.editing = None;
+        self.site = "".to_string();
+        self.site_username = "".to_string();
+        self.site_password = "".to_string();
+        self.url = "".to_string();
+        self.notes = "".to_string();
+        self.tags = "".to_string();
+        self.favourite = false;
+        self.otp = "".to_string();
+    }
+
+    fn clear_backup_secrets(&mut self) {
+        self.backup_password = "".to_string();
+        self.backup_password_confirm = "".to_string();
+        self.backup_recovery_key = "".to_string();
+    }
+
+    //The backup password when one is typed, else the recovery key
+    fn backup_secret(&self) -> Result<backup::Secret<'_>, String> {
+        match (self.backup_password.is_empty(), self.backup_recovery_key.trim().is_empty()) {
+            (false, true) => Ok(backup::Secret::Password(&self.backup_password)),
+            (true, false) => Ok(backup::Secret::RecoveryKey(&self.backup_recovery_key)),
+            (true, true) => Err("Type the backup password or your recovery key".to_string()),
+            (false, false) => Err("Type either the backup password or your recovery key, not both".to_string()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Data, PartialEq)]
@@ -67,9 +269,19 @@ enum ViewSelector {
     Login,
     Register,
     Credentials,
+    Fingers,
+    Recovery,
+    Security,
+    History,
+    Breaches,
+    Health,
+    Import,
+    Backup,
+    Export,
 }
 
 const CELL_WIDTH: f64 = 150.0;
+const STRENGTH_CELL_WIDTH: f64 = 100.0;
 const CELL_HEIGHT: f64 = 50.0;
 const HEADER_COLOR: Color = Color::rgb8(0x2e, 0x2e, 0x2e);
 const TEXT_COLOR: Color = Color::WHITE;
@@ -80,7 +292,36 @@ const SHOW_REGISTER: Selector = Selector::new("show-register");
 const SHOW_LOGIN: Selector = Selector::new("show-login");
 const SHOW_CREDENTIALS: Selector = Selector::new("show-credentials");
 const UPDATE_CREDENTIALS: Selector<Arc<Vec<Credential>>> = Selector::new("update-credentials");
-
+const EDIT_CREDENTIAL: Selector<i32> = Selector::new("edit-credential");
+const SELECT_CREDENTIAL: Selector<i32> = Selector::new("select-credential");
+const RESTORE_PASSWORD: Selector<String> = Selector::new("restore-password");
+const COPY_PASSWORD: Selector<i32> = Selector::new("copy-password");
+const COPY_CODE: Selector<i32> = Selector::new("copy-code");
+const EDIT_ITEM: Selector<i64> = Selector::new("edit-item");
+const DELETE_ITEM: Selector<i64> = Selector::new("delete-item");
+const CHECK_PASSWORD: Selector = Selector::new("check-password");
+
+//Size of the images produced by fingerprintCapture.exe
+const FINGERPRINT_ROWS: usize = 80;
+const FINGERPRINT_COLS: usize = 64;
+//Minimum number of matched minutiae for two impressions to be considered the same finger
+const MATCH_THRESHOLD: usize = 120;
+//Number of impressions taken at registration, can be overridden with ENROLL_IMPRESSIONS in .env
+const DEFAULT_ENROLL_IMPRESSIONS: usize = 3;
+const MIN_MASTER_PASSWORD_LEN: usize = 12;
+//Number of index candidates fully matched during identification, can be overridden with INDEX_CANDIDATES
+const DEFAULT_INDEX_CANDIDATES: usize = 5;
+//File holding the secret the index keys are sealed with, kept out of users.db, can be
+//overridden with INDEX_KEY_PATH
+const DEFAULT_INDEX_KEY_PATH: &str = "index.key";
+//Previous passwords kept per credential, can be overridden with PASSWORD_HISTORY_RETENTION (0 keeps none)
+const DEFAULT_HISTORY_RETENTION: usize = 5;
+//Passwords older than this are listed in the health report, can be overridden with PASSWORD_MAX_AGE_DAYS
+const DEFAULT_PASSWORD_MAX_AGE_DAYS: u64 = 365;
+//A plaintext export needs a fingerprint verified this many seconds before at most
+const EXPORT_VERIFICATION_SECONDS: u64 = 120;
+//Shown when the matched finger holds no usable key release
+const NO_KEY_RELEASE: &str = "This finger cannot unlock the vault. Unlock it with your master password or recovery key (Fingerprint unavailable?), then enrol the finger again in the Fingers view.";
 
 // Function to detect minutiae in the fingerprint image
 fn detect_minutiae(image: &Vec<Vec<u8>>) -> Vec<(usize, usize)> {
@@ -173,6 +414,16 @@ fn binary_to_image(image_data: &Vec<u8>, output_path: &str) -> Result<(), Box<dy
     Ok(())
 }
 
+//Creates a file only its owner can read, fails if the path exists even if it was created since
+//it was checked
+fn write_new_private_file(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(data)
+}
+
 
 // Function to flatten Vec<Vec<u8>> into Vec<u8>
 fn flatten(vec: Vec<Vec<u8>>) -> Vec<u8> {
@@ -184,6 +435,230 @@ fn reconstruct(flat: Vec<u8>, rows: usize, cols: usize) -> Vec<Vec<u8>> {
     flat.chunks(cols).map(|chunk| chunk.to_vec()).collect()
 }
 
+// Splits a stored template into the impressions it was enrolled with.
+// Templates saved before multi-impression enrollment hold a single image.
+fn split_impressions(flat: Vec<u8>) -> Vec<Vec<Vec<u8>>> {
+    let size = FINGERPRINT_ROWS * FINGERPRINT_COLS;
+    if flat.len() < size || flat.len() % size != 0 {
+        return vec![reconstruct(flat, FINGERPRINT_ROWS, FINGERPRINT_COLS)];
+    }
+    flat.chunks(size)
+        .map(|chunk| reconstruct(chunk.to_vec(), FINGERPRINT_ROWS, FINGERPRINT_COLS))
+        .collect()
+}
+
+// Runs the whole extraction pipeline on a captured image
+fn process_fingerprint(image_path: &str) -> Vec<Vec<u8>> {
+    let hist = histogram_equalization(image_path);
+    let bin = binarization(hist.clone(), 128);
+    let thin = thin(&bin);
+    let minutia = mark_minutia(&thin);
+    remove_false_minutia(thin, minutia, 10.0, 0.5)
+}
+
+// Best-of-N matching: the score of a probe is its best score against any stored impression
+fn best_match(probe: &Vec<Vec<u8>>, impressions: &Vec<Vec<Vec<u8>>>) -> usize {
+    impressions
+        .iter()
+        .map(|impression| minutiae_matching(probe, impression).len())
+        .max()
+        .unwrap_or(0)
+}
+
+//Ridge endings and bifurcations of a skeleton image. The index needs real minutiae: the points
+//of detect_minutiae are every pixel along the ridges, their triangles look alike on every finger.
+fn index_minutiae(image: &Vec<Vec<u8>>) -> Vec<index::Point> {
+    mark_minutia(image)
+        .into_iter()
+        .map(|minutia| (minutia.x, minutia.y, minutia.minutia_type == MinutiaType::Bifurcation))
+        .collect()
+}
+
+//Sealed index keys of a template
+fn index_keys(impressions: &[Vec<Vec<u8>>]) -> Result<Vec<i64>, String> {
+    let points: Vec<Vec<index::Point>> = impressions.iter().map(index_minutiae).collect();
+    Ok(index::seal(&index_secret()?, &index::template_keys(&points, FINGERPRINT_ROWS, FINGERPRINT_COLS)))
+}
+
+fn index_secret_path() -> PathBuf {
+    env::var("INDEX_KEY_PATH")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_INDEX_KEY_PATH))
+}
+
+//Secret the index keys are sealed with, created at startup by ensure_index_secret
+fn index_secret() -> Result<Vec<u8>, String> {
+    let path = index_secret_path();
+    std::fs::read(&path).map_err(|e| format!("Failed to read the index key {}: {}", path.display(), e))
+}
+
+//Creates the index secret on first start. The keys sealed with a lost secret, or stored in
+//clear by older versions, cannot be found anymore so the index is cleared, each template is
+//indexed again at the next login with its username.
+async fn ensure_index_secret(pool: &SqlitePool) {
+    let path = index_secret_path();
+    if path.exists() {
+        return;
+    }
+    let mut secret = vec![0u8; 32];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut secret);
+    std::fs::write(&path, &secret).expect("Failed to write the index key");
+    println!("Created the index key {}", path.display());
+    clear_template_index(pool).await.expect("Failed to clear the index");
+}
+
+//Only the cancelable transform of the impressions is stored, never the skeleton images
+fn protect_template(impressions: &[Vec<Vec<u8>>], key: &[u8; 32]) -> Vec<u8> {
+    let points: Vec<Vec<(usize, usize)>> = impressions.iter().map(detect_minutiae).collect();
+    protection::protect(&points, key, FINGERPRINT_ROWS, FINGERPRINT_COLS)
+}
+
+//Protected template, key release helper data and wrapped vault key of a finger. The output of
+//the fuzzy extractor keys the template transform and wraps the vault key, none is stored.
+fn bind_template(impressions: &[Vec<Vec<u8>>], key: &VaultKey) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let points: Vec<Vec<(usize, usize)>> = impressions.iter().map(detect_minutiae).collect();
+    let (derived, helper) = fuzzy::enroll(&points, FINGERPRINT_ROWS, FINGERPRINT_COLS);
+    let wrapping_key = VaultKey::from_bytes(&derived).unwrap();
+    let wrapped = vault::encrypt(&wrapping_key, "vault_key", key.as_bytes());
+    (protect_template(impressions, &derived), helper, wrapped)
+}
+
+//Best-of-N score of a probe against a stored template, with the fuzzy extractor output the
+//probe reproduced from the key release data of the template
+fn template_score(probe: &Vec<Vec<u8>>, template: &[u8], key: &[u8; 32]) -> usize {
+    if protection::is_protected(template) {
+        protection::score(&detect_minutiae(probe), template, key, FINGERPRINT_ROWS, FINGERPRINT_COLS)
+    } else {
+        best_match(probe, &split_impressions(template.to_vec()))
+    }
+}
+
+fn index_candidates() -> usize {
+    env::var("INDEX_CANDIDATES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|&count| count > 0)
+        .unwrap_or(DEFAULT_INDEX_CANDIDATES)
+}
+
+//Indexes and protects the templates stored as raw skeleton images by older versions,
+//the index has to be built first since the raw images are gone once protected.
+//Users registered before the vault was encrypted get their vault key here, bound to the raw
+//impressions of their fingers, so that no key is ever generated when logging in.
+async fn upgrade_legacy_templates(pool: &SqlitePool) {
+    ensure_index_secret(pool).await;
+    let unindexed = get_unindexed_templates(pool).await.expect("Failed to load templates");
+    for template in unindexed.iter().filter(|template| !protection::is_protected(&template.template)) {
+        let keys = match index_keys(&split_impressions(template.template.clone())) {
+            Ok(keys) => keys,
+            Err(e) => {
+                eprintln!("Failed to index template {}: {}", template.id, e);
+                continue;
+            }
+        };
+        if let Err(e) = save_template_keys(pool, template.id, &keys).await {
+            eprintln!("Failed to index template {}: {}", template.id, e);
+        }
+    }
+
+    let templates = get_all_templates(pool).await.expect("Failed to load templates");
+    let mut vault_keys: std::collections::HashMap<i64, VaultKey> = std::collections::HashMap::new();
+    for (username, template) in templates.iter().filter(|(_, template)| !protection::is_protected(&template.template)) {
+        let impressions = split_impressions(template.template.clone());
+        if !vault_keys.contains_key(&template.user_id) {
+            let (_, releases) = count_key_releases(pool, template.user_id).await.expect("Failed to count key releases");
+            if releases == 0 {
+                println!("Creating the vault key of {}", username);
+                vault_keys.insert(template.user_id, VaultKey::generate());
+            }
+        }
+        let protected = match vault_keys.get(&template.user_id) {
+            Some(key) => {
+                let (protected, helper, wrapped) = bind_template(&impressions, key);
+                //Kept raw on failure so that the next start binds it again
+                if let Err(e) = save_key_release(pool, template.id, helper, wrapped).await {
+                    eprintln!("Failed to bind the vault key to template {}: {}", template.id, e);
+                    continue;
+                }
+                protected
+            }
+            //No vault key to bind: the transform key is thrown away and the finger has to be
+            //enrolled again, but the raw images are still removed
+            None => protect_template(&impressions, &rand::random()),
+        };
+        if let Err(e) = update_template(pool, template.id, protected).await {
+            eprintln!("Failed to protect template {}: {}", template.id, e);
+        }
+    }
+}
+
+//Prints the penetration rate against accuracy of the index. Enrolled templates are protected
+//so the measure runs on a folder of captures named like FVC datasets: <finger>_<impression>.bmp,
+//data/index_eval by default which is described in INDEX.md
+fn print_index_report(dataset: &str) {
+    let mut fingers: std::collections::BTreeMap<String, Vec<Vec<index::Point>>> = std::collections::BTreeMap::new();
+    let entries = match std::fs::read_dir(dataset) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", dataset, e);
+            return;
+        }
+    };
+    let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        let stem = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem.to_string(),
+            None => continue,
+        };
+        let finger = match stem.split_once('_') {
+            Some((finger, _)) => finger.to_string(),
+            None => continue,
+        };
+        let image = process_fingerprint(path.to_str().unwrap());
+        fingers.entry(finger).or_default().push(index_minutiae(&image));
+    }
+    let points: Vec<index::IndexedFinger> = fingers
+        .into_values()
+        .enumerate()
+        .map(|(id, impressions)| (id as i64, impressions))
+        .collect();
+
+    println!("{} fingers", points.len());
+    println!("shortlist | penetration rate | accuracy");
+    for (limit, penetration, accuracy) in index::report(&points, FINGERPRINT_ROWS, FINGERPRINT_COLS, &[1, 2, 5, 10, 20]) {
+        println!("{:>9} | {:>15.1}% | {:>7.1}%", limit, penetration * 100.0, accuracy * 100.0);
+    }
+}
+
+fn enroll_impressions() -> usize {
+    env::var("ENROLL_IMPRESSIONS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|&count| count > 0)
+        .unwrap_or(DEFAULT_ENROLL_IMPRESSIONS)
+}
+
+fn history_retention() -> usize {
+    env::var("PASSWORD_HISTORY_RETENTION")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_RETENTION)
+}
+
+fn password_max_age_days() -> u64 {
+    env::var("PASSWORD_MAX_AGE_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_PASSWORD_MAX_AGE_DAYS)
+}
+
+//Downloaded Have I Been Pwned SHA-1 file, or directory of range files
+fn hibp_path() -> Option<PathBuf> {
+    env::var("HIBP_PATH").ok().filter(|path| !path.is_empty()).map(PathBuf::from)
+}
 
 #[tokio::main]
 async fn main() {
@@ -195,11 +670,20 @@ async fn main() {
     let database_path = current_dir.join("users.db");
     let database_url = format!("sqlite://{}", database_path.to_str().unwrap());
     
-    let pool = SqlitePool::connect(&database_url).await.unwrap();
+    let pool = connect(&database_url).await.unwrap();
+
+    run_migrations(&pool).await.expect("Failed to migrate the database");
+    println!("Database schema version {}", schema_version(&pool).await.expect("Failed to read the schema version"));
+    upgrade_legacy_templates(&pool).await;
 
-    create_tables(&pool).await.expect("Failed to create tables");
+    let args: Vec<String> = env::args().collect();
+    if let Some(position) = args.iter().position(|arg| arg == "--index-report") {
+        let dataset = args.get(position + 1).map(|dir| dir.as_str()).unwrap_or("data/index_eval");
+        print_index_report(dataset);
+        return;
+    }
     
-    let size = (800.0, 450.0);
+    let size = (1200.0, 700.0);
     let main_windows = WindowDesc::new(build_ui(pool.clone().into()))
     .title("Bioguard")
     .window_size(size);
@@ -207,7 +691,7 @@ async fn main() {
     let initial_state = AppState::new();
 
     AppLauncher::with_window(main_windows)
-    //.delegate(AppDelegate::new(pool))
+    .delegate(Delegate { pool: pool.clone().into() })
     .launch(initial_state)
     .expect("Failed to launch application");
 }
@@ -227,37 +711,62 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
 
     let login_button = Button::new("Login").on_click(move |_ctx, data: &mut AppState, _env| {
         let _username = data.username.clone();
-        let logged = my_child_login(_username.clone(), Arc::clone(&pool_clone1));
-        //TODO Handle login failure
-
-        println!("Logged: {}", logged);
-
-        if logged {
-            data.view = ViewSelector::Credentials;
-            my_child_update(&pool_clone1, &_username, None, None, None, data);
+        match my_child_login(_username.clone(), Arc::clone(&pool_clone1)) {
+            Ok(key) => {
+                data.login_message = "".to_string();
+                my_child_open_vault(&pool_clone1, &_username, key, data);
+            }
+            Err(message) => data.login_message = message,
         }
+    });
 
-        //TDOO notify not logged in
-
+    //Identification mode: no username, the finger is searched among every enrolled template
+    let pool_identify = Arc::clone(&pool);
+    let touch_button = Button::new("Touch sensor").on_click(move |_ctx, data: &mut AppState, _env| {
+        match my_child_identify(Arc::clone(&pool_identify)) {
+            Ok((username, key)) => {
+                data.login_message = "".to_string();
+                data.username = username.clone();
+                my_child_open_vault(&pool_identify, &username, key, data);
+            }
+            Err(message) => data.login_message = message,
+        }
     });
 
     let pool_clone2 = Arc::clone(&pool);
     let register_button_log = Button::new("Register").on_click({
         move |_ctx, data: &mut AppState, _env| {
+            data.register_message = "".to_string();
             data.view = ViewSelector::Register;
             //_ctx.submit_command(DruidCommand::new(SHOW_REGISTER, (), Target::Global));
             }
     });
     
+    let message_log = Label::new(|data: &AppState, _env: &_| data.login_message.clone())
+    .with_line_break_mode(LineBreaking::WordWrap)
+    .fix_width(400.0)
+    .padding(5.0);
+
+    let recovery_button_log = Button::new("Fingerprint unavailable?").on_click(|_ctx, data: &mut AppState, _env| {
+        data.login_message = "".to_string();
+        data.security_message = "".to_string();
+        data.view = ViewSelector::Recovery;
+    });
+
     let login_view = Flex::column()
     .with_child(label_log)
     .with_spacer(20.0)
+    .with_child(touch_button)
+    .with_spacer(20.0)
     .with_child(username_input)
     .with_spacer(20.0)
     .with_child(login_button)
+    .with_child(message_log)
     .with_spacer(20.0)
     .with_child(register_button_log)
-    .with_spacer(60.0)
+    .with_spacer(20.0)
+    .with_child(recovery_button_log)
+    .with_spacer(40.0)
     .with_child(quit_button);
 
 
@@ -265,10 +774,15 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
     // REGISTER VIEW
     let label_reg = Label::new("Bioguard Register").padding(5.0);
     
-    let info = Label::new("To register your account with your fingerprint,\nPlease click on the Register button").padding(5.0);
+    let info = Label::new(format!(
+        "To register your account with your fingerprint,\nPlease click on the Register button\nand place the same finger {} times on the sensor",
+        enroll_impressions()
+    )).padding(5.0);
 
     let username_input = TextBox::new().with_placeholder("Username").lens(AppState::username);
     
+    let finger_button_reg = finger_selector();
+
     let pool_clone3 = Arc::clone(&pool);
     let register_button_reg = Button::new("Register").on_click(
         move |_ctx, data: &mut AppState, _env| {
@@ -276,17 +790,17 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
         
             //let pool_clone3 = Arc::clone(&pool);
             let _username = data.username.clone();
-            let mut ret = 0;
-            my_child_register(_username.clone(), Arc::clone(&pool_clone3));
+            let key = my_child_register(_username.clone(), data.finger, Arc::clone(&pool_clone3));
 
-            /*if ret == 1 {
-                println!("Launch new windows");
-            }*/
             println!("Registering user");
 
-            data.view = ViewSelector::Credentials;
-
-            my_child_update(&pool_clone3, &_username, None, None, None, data);
+            match key {
+                Ok(key) => {
+                    data.register_message = "".to_string();
+                    my_child_open_vault(&pool_clone3, &_username, key, data);
+                }
+                Err(message) => data.register_message = message,
+            }
             }
         );
 
@@ -295,8 +809,14 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
     });
 
     let back_button = Button::new("Back to login").on_click(|_ctx, data: &mut AppState, _env| {
+        data.register_message = "".to_string();
         data.view = ViewSelector::Login;
     });
+
+    let message_reg = Label::new(|data: &AppState, _env: &_| data.register_message.clone())
+    .with_line_break_mode(LineBreaking::WordWrap)
+    .fix_width(400.0)
+    .padding(5.0);
     
     let register_view = Flex::column()
     .with_child(label_reg)
@@ -305,7 +825,10 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
     .with_spacer(20.0)
     .with_child(info)
     .with_spacer(20.0)
+    .with_child(finger_button_reg)
+    .with_spacer(20.0)
     .with_child(register_button_reg)
+    .with_child(message_reg)
     .with_spacer(60.0)
     .with_child(back_button)
     .with_spacer(20.0)
@@ -319,23 +842,78 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
     let site_username_input = TextBox::new().with_placeholder("Site Username").lens(AppState::site_username);
     let site_password_input = TextBox::new().with_placeholder("Site Password").lens(AppState::site_password);
 
+    let generate_button = Button::new("Generate").on_click(|_ctx, data: &mut AppState, _env| {
+        match generator::generate(&data.generator) {
+            Some(password) => data.site_password = password,
+            None => data.status_message = "Select at least one character class".to_string(),
+        }
+    });
+
+    let generator_options_button = Button::dynamic(|data: &AppState, _env| {
+        if data.show_generator { "Hide options".to_string() } else { "Options".to_string() }
+    }).on_click(|_ctx, data: &mut AppState, _env| {
+        data.show_generator = !data.show_generator;
+    });
+
+    let generator_panel = generator_panel().lens(AppState::generator);
+    //Estimated live while typing or generating
+    let strength_label = Label::new(|data: &AppState, _env: &_| {
+        match data.password_breaches {
+            Some(count) if count > 0 => format!("{}, seen {} times in breaches", data.password_strength, count),
+            _ => data.password_strength.clone(),
+        }
+    });
+    let url_input = TextBox::new().with_placeholder("URL").lens(AppState::url);
+    let notes_input = TextBox::multiline().with_placeholder("Notes").fix_height(60.0).lens(AppState::notes);
+    let tags_input = TextBox::new().with_placeholder("Tags (comma separated)").lens(AppState::tags);
+    let favourite_input = Checkbox::new("Favourite").lens(AppState::favourite);
+    let otp_input = TextBox::new().with_placeholder("2FA otpauth:// URI or secret").lens(AppState::otp);
+
     let pool_save  = Arc::clone(&pool);
 
-    let save_button = Button::new("Add Credential").on_click({
+    let save_button = Button::dynamic(|data: &AppState, _env| {
+        if data.editing.is_some() { "Save Changes".to_string() } else { "Add Credential".to_string() }
+    }).on_click({
         let pool_clone = Arc::clone(&pool);
         move |_ctx, data: &mut AppState, _env| {
 
             let user = data.username.clone();
             let binding = pool.clone();
-            let site = data.site.clone();
-            let site_username = data.site_username.clone();
-            let site_password = data.site_password.clone();
+            let mut credential = data.form_credential();
+            //Bare secrets are stored as a full otpauth URI
+            if !credential.otp.is_empty() {
+                match totp::parse(&credential.otp) {
+                    Some(otp) => credential.otp = otp.to_uri(),
+                    None => {
+                        data.status_message = "Invalid 2FA secret, expected an otpauth:// URI or a base32 secret".to_string();
+                        return;
+                    }
+                }
+            }
             
-            my_child_update(&binding, &user, Some(&site), Some(&site_username), Some(&site_password), data);
+            let saved = match data.editing {
+                Some(_) => my_child_edit(&binding, &user, &credential, data),
+                None => my_child_update(&binding, &user, Some(&credential), data),
+            };
+
+            //A refused or failed save keeps what was typed
+            if saved {
+                data.clear_form();
+            }
+        }
+    });
+
+    let cancel_edit_button = Button::new("Cancel Edit").on_click(|_ctx, data: &mut AppState, _env| {
+        data.clear_form();
+    });
 
-            data.site = "".to_string();
-            data.site_username = "".to_string();
-            data.site_password = "".to_string();
+    let pool_history = Arc::clone(&pool);
+    let history_button = Button::new("Password History").on_click(move |_ctx, data: &mut AppState, _env| {
+        if let Some(id) = data.editing {
+            let user = data.username.clone();
+            if my_child_history(&pool_history, &user, id, data) {
+                data.view = ViewSelector::History;
+            }
         }
     });
 
@@ -343,14 +921,44 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
         let pool_clone = Arc::clone(&pool_save);
         move |_ctx, data: &mut AppState, _env| {
             let user = data.username.clone();
-            let site = data.site.clone();
+            let selected = data.selected;
             let binding = Arc::clone(&pool_clone);
 
-            my_child_delete(&binding, &user, Some(&site), data);
+            my_child_delete(&binding, &user, selected, data);
+
+            my_child_update(&binding, &user, None, data);
+        }
+    });
+
+    let selected_label = Label::new(|data: &AppState, _env: &_| {
+        match data.selected.and_then(|id| data.credentials.iter().find(|cred| cred.id == id)) {
+            Some(cred) => format!("Selected: {} ({})", cred.site, cred.site_username),
+            None => "No credential selected".to_string(),
+        }
+    });
 
-            my_child_update(&binding, &user, None, None, None, data);
+    let details_label = Label::new(|data: &AppState, _env: &_| {
+        match data.selected.and_then(|id| data.credentials.iter().find(|cred| cred.id == id)) {
+            Some(cred) => format!(
+                "URL: {}\nTags: {}\nNotes: {}\nCreated: {}   Modified: {}   Last used: {}{}",
+                cred.url,
+                cred.tags,
+                cred.notes,
+                cred.created_at,
+                cred.modified_at,
+                if cred.last_used_at.is_empty() { "never" } else { cred.last_used_at.as_str() },
+                if cred.breaches > 0 { format!("\nPassword seen {} times in breaches, change it", cred.breaches) } else { "".to_string() },
+            ),
+            None => "".to_string(),
         }
-    });    
+    })
+    .with_line_break_mode(LineBreaking::WordWrap)
+    .padding(5.0);    
+
+    let message_cr = Label::new(|data: &AppState, _env: &_| data.status_message.clone())
+    .with_line_break_mode(LineBreaking::WordWrap)
+    .fix_width(400.0)
+    .padding(5.0);
 
 
 
@@ -388,6 +996,28 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
        .width(CELL_WIDTH)
        .padding(5.0)
        .border(Color::BLACK, 1.0),
+   )
+   .with_child(
+       SizedBox::new(
+           Label::new("Strength")
+               .with_text_color(TEXT_COLOR)
+               .center()
+               .background(HEADER_COLOR)
+       )
+       .width(STRENGTH_CELL_WIDTH)
+       .padding(5.0)
+       .border(Color::BLACK, 1.0),
+   )
+   .with_child(
+       SizedBox::new(
+           Label::new("2FA code")
+               .with_text_color(TEXT_COLOR)
+               .center()
+               .background(HEADER_COLOR)
+       )
+       .width(CELL_WIDTH)
+       .padding(5.0)
+       .border(Color::BLACK, 1.0),
    );
 
     // List of credentials
@@ -395,7 +1025,12 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
     Flex::row()
         .with_child(
             SizedBox::new(
-                Label::new(|cred: &Credential, _env: &_| format!("{}", cred.site))
+                Label::new(|cred: &Credential, _env: &_| format!(
+                    "{}{}{}",
+                    if cred.breaches > 0 { "⚠ " } else { "" },
+                    if cred.favourite { "★ " } else { "" },
+                    cred.site
+                ))
                     .with_text_color(TEXT_COLOR)
                     .center()
                     .background(CELL_BG_COLOR)
@@ -426,13 +1061,67 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
             .padding(5.0)
             .border(Color::BLACK, 1.0),
         )
+        .with_child(
+            SizedBox::new(
+                Label::new(|cred: &Credential, _env: &_| strength::label(cred.strength).to_string())
+                    .with_text_color(TEXT_COLOR)
+                    .center()
+                    .background(CELL_BG_COLOR)
+            )
+            .width(STRENGTH_CELL_WIDTH)
+            .padding(5.0)
+            .border(Color::BLACK, 1.0),
+        )
+        .with_child(
+            SizedBox::new(
+                Label::new(|cred: &Credential, _env: &_| cred.otp_code.clone())
+                    .with_text_color(TEXT_COLOR)
+                    .center()
+                    .background(CELL_BG_COLOR)
+            )
+            .width(CELL_WIDTH)
+            .padding(5.0)
+            .border(Color::BLACK, 1.0),
+        )
+        .with_child(
+            //The row only knows its credential, the delegate loads it into the form
+            Button::new("Edit").on_click(|ctx, cred: &mut Credential, _env| {
+                ctx.submit_command(DruidCommand::new(EDIT_CREDENTIAL, cred.id, Target::Global));
+            })
+            .padding(5.0),
+        )
+        .with_child(
+            Button::new("Select").on_click(|ctx, cred: &mut Credential, _env| {
+                ctx.submit_command(DruidCommand::new(SELECT_CREDENTIAL, cred.id, Target::Global));
+            })
+            .padding(5.0),
+        )
+        .with_child(
+            Button::new("Copy").on_click(|ctx, cred: &mut Credential, _env| {
+                ctx.submit_command(DruidCommand::new(COPY_PASSWORD, cred.id, Target::Global));
+            })
+            .padding(5.0),
+        )
+        .with_child(
+            //Only shown for the credentials having a 2FA secret
+            druid::widget::Either::new(
+                |cred: &Credential, _env| !cred.otp.is_empty(),
+                Button::new("Copy code").on_click(|ctx, cred: &mut Credential, _env| {
+                    ctx.submit_command(DruidCommand::new(COPY_CODE, cred.id, Target::Global));
+                })
+                .padding(5.0),
+                SizedBox::empty(),
+            ),
+        )
     })
     .lens(AppState::credentials);
 
     // Table view
     let table = Flex::column()
     .with_child(headers)
-    .with_child(credentials_list);
+    .with_child(Scroll::new(credentials_list).vertical().fix_height(300.0))
+    .with_spacer(10.0)
+    .with_child(details_label);
 
     let quit_button = Button::new("Quit").on_click(|_ctx, _data: &mut AppState, _env| {
         std::process::exit(0);
@@ -441,13 +1130,189 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
     let logout_button = Button::new("Logout").on_click(|_ctx, data: &mut AppState, _env| {
         data.view = ViewSelector::Login;
         data.username = "".to_string();
+        data.fingers = Arc::new(Vec::new());
+        data.fingers_message = "".to_string();
+        data.credentials = Arc::new(Vec::new());
+        data.clear_form();
+        data.selected = None;
+        data.history = Arc::new(Vec::new());
+        data.category = None;
+        data.items = Arc::new(Vec::new());
+        data.clear_item_form();
+        data.status_message = "".to_string();
+        data.vault_key = None;
+        data.recovery_key = "".to_string();
+        data.breach_message = "".to_string();
+        data.health = Arc::new(Vec::new());
+        data.health_message = "".to_string();
+        data.import_path = "".to_string();
+        data.import_rows = Arc::new(Vec::new());
+        data.import_message = "".to_string();
+        data.import_items = Arc::new(Vec::new());
+        data.kdbx_password = "".to_string();
+        data.kdbx_password_confirm = "".to_string();
+        data.clear_backup_secrets();
+        data.backup_path = "".to_string();
+        data.backup_message = "".to_string();
+        data.export_verified_at = None;
+        data.export_confirmed = false;
+        data.export_path = "".to_string();
+        data.export_message = "".to_string();
+    });
+
+    let fingers_button = Button::new("Manage fingers").on_click(|_ctx, data: &mut AppState, _env| {
+        data.fingers_message = "".to_string();
+        data.view = ViewSelector::Fingers;
+    });
+
+    let breaches_button = Button::new("Breach report").on_click(|_ctx, data: &mut AppState, _env| {
+        check_breaches(data);
+        data.view = ViewSelector::Breaches;
+    });
+
+    let pool_health = Arc::clone(&pool);
+    let health_button = Button::new("Health report").on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        my_child_health(&pool_health, &user, data);
+        data.view = ViewSelector::Health;
+    });
+
+    let import_button = Button::new("Import").on_click(|_ctx, data: &mut AppState, _env| {
+        data.import_rows = Arc::new(Vec::new());
+        data.import_items = Arc::new(Vec::new());
+        data.import_message = "".to_string();
+        data.view = ViewSelector::Import;
+    });
+
+    let backup_button = Button::new("Backup").on_click(|_ctx, data: &mut AppState, _env| {
+        data.backup_message = "".to_string();
+        data.backup_replace = false;
+        data.view = ViewSelector::Backup;
+    });
+
+    let export_button = Button::new("Plaintext export").on_click(|_ctx, data: &mut AppState, _env| {
+        data.export_verified_at = None;
+        data.export_confirmed = false;
+        data.export_message = "".to_string();
+        data.view = ViewSelector::Export;
+    });
+
+    let security_button = Button::new("Security").on_click(|_ctx, data: &mut AppState, _env| {
+        data.security_message = "".to_string();
+        data.recovery_key = "".to_string();
+        data.view = ViewSelector::Security;
     });
         
+    //Site logins or one kind of items
+    let mut categories = Flex::row().with_child(Button::new("Logins").on_click(|_ctx, data: &mut AppState, _env| {
+        data.category = None;
+        data.clear_item_form();
+    }));
+    for kind in ItemKind::ALL {
+        categories = categories.with_spacer(10.0).with_child(Button::new(kind.label()).on_click(move |_ctx, data: &mut AppState, _env| {
+            data.category = Some(kind);
+            data.clear_item_form();
+        }));
+    }
+
+    let category_label = Label::new(|data: &AppState, _env: &_| {
+        data.category.map(|kind| kind.label()).unwrap_or("Logins").to_string()
+    }).padding(5.0);
+
+    // ITEMS PANEL
+    let item_title_input = TextBox::new().with_placeholder("Title").lens(AppState::item_title);
+
+    let item_fields_list = List::new(|| {
+        Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(Label::new(|field: &ItemField, _env: &_| field.label.clone()))
+            .with_child(TextBox::multiline().fix_width(250.0).lens(ItemField::value))
+            .padding((0.0, 0.0, 0.0, 10.0))
+    })
+    .lens(AppState::item_fields);
+
+    let pool_item = Arc::clone(&pool);
+    let save_item_button = Button::dynamic(|data: &AppState, _env| {
+        if data.editing_item.is_some() { "Save Changes".to_string() } else { "Add Item".to_string() }
+    }).on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        my_child_save_item(&pool_item, &user, data);
+    });
+
+    let cancel_item_button = Button::new("Cancel Edit").on_click(|_ctx, data: &mut AppState, _env| {
+        data.clear_item_form();
+    });
+
+    let items_list = List::new(|| {
+        Flex::row()
+            .with_child(
+                SizedBox::new(
+                    Label::new(|item: &Item, _env: &_| item.title.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|item: &Item, _env: &_| item.content.summary())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH * 2.0)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                Button::new("Edit").on_click(|ctx, item: &mut Item, _env| {
+                    ctx.submit_command(DruidCommand::new(EDIT_ITEM, item.id, Target::Global));
+                })
+                .padding(5.0),
+            )
+            .with_child(
+                Button::new("Delete").on_click(|ctx, item: &mut Item, _env| {
+                    ctx.submit_command(DruidCommand::new(DELETE_ITEM, item.id, Target::Global));
+                })
+                .padding(5.0),
+            )
+    })
+    //Read only, the rows only submit commands
+    .lens(druid::lens::Map::new(
+        |data: &AppState| -> Arc<Vec<Item>> {
+            Arc::new(data.items.iter().filter(|item| Some(item.content.kind()) == data.category).cloned().collect())
+        },
+        |_data: &mut AppState, _items: Arc<Vec<Item>>| {},
+    ));
+
+    let items_panel = Flex::row()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::column()
+            .with_child(item_title_input)
+            .with_spacer(20.0)
+            .with_child(Scroll::new(item_fields_list).vertical().fix_height(300.0))
+            .with_spacer(20.0)
+            .with_child(save_item_button)
+            .with_spacer(20.0)
+            .with_child(view_if(|data: &AppState| data.editing_item.is_some(), cancel_item_button))
+        )
+        .with_spacer(40.0)
+        .with_child(Scroll::new(items_list).vertical().fix_height(400.0));
+
     let credentials_view = 
     Flex::column()
     .with_child(label_cr)
-    .with_spacer(40.0)
-    .with_child(
+    .with_spacer(10.0)
+    .with_child(categories)
+    .with_child(category_label)
+    .with_spacer(10.0)
+    .with_child(view_if(|data: &AppState| data.category.is_some(), items_panel))
+    .with_child(view_if(
+        |data: &AppState| data.category.is_none(),
         Flex::row()
             .with_child(
                 Flex::column()
@@ -455,126 +1320,1655 @@ fn build_ui(pool: Arc<SqlitePool>) -> impl Widget<AppState> {
                 .with_spacer(20.0)
                 .with_child(site_username_input)
                 .with_spacer(20.0)
-                .with_child(site_password_input)
+                .with_child(
+                    Flex::row()
+                    .with_child(site_password_input)
+                    .with_spacer(10.0)
+                    .with_child(generate_button)
+                    .with_spacer(10.0)
+                    .with_child(generator_options_button)
+                )
+                .with_spacer(5.0)
+                .with_child(strength_label)
+                .with_spacer(10.0)
+                .with_child(view_if(|data: &AppState| data.show_generator, generator_panel))
+                .with_spacer(20.0)
+                .with_child(url_input)
+                .with_spacer(20.0)
+                .with_child(notes_input)
+                .with_spacer(20.0)
+                .with_child(tags_input)
+                .with_spacer(20.0)
+                .with_child(favourite_input)
+                .with_spacer(20.0)
+                .with_child(otp_input)
                 .with_spacer(20.0)
                 .with_child(save_button)
                 .with_spacer(20.0)
+                .with_child(view_if(
+                    |data: &AppState| data.editing.is_some(),
+                    Flex::row().with_child(cancel_edit_button).with_spacer(10.0).with_child(history_button),
+                ))
+                .with_child(selected_label)
+                .with_spacer(10.0)
                 .with_child(delete_credentials_button)
             )
             .with_spacer(40.0)
             .with_child(table)
+    ))
+    .with_child(message_cr)
+    .with_child(
+        Flex::row()
+            .with_child(fingers_button)
+            .with_spacer(20.0)
+            .with_child(breaches_button)
+            .with_spacer(20.0)
+            .with_child(health_button)
+            .with_spacer(20.0)
+            .with_child(import_button)
+            .with_spacer(20.0)
+            .with_child(backup_button)
+            .with_spacer(20.0)
+            .with_child(export_button)
+            .with_spacer(20.0)
+            .with_child(security_button)
+            .with_spacer(20.0)
+            .with_child(logout_button)
     )
-    .with_spacer(60.0)
-    .with_child(logout_button)
     .with_spacer(20.0)
     .with_child(quit_button);
 
 
-    // MAIN VIEW
-    let main_view = Flex::column().with_child(druid::widget::Either::new(
-        |data: &AppState, _env| data.view == ViewSelector::Login,
-        login_view,
-        druid::widget::Either::new(
-            |data: &AppState, _env| data.view == ViewSelector::Register,
-            register_view,
-            credentials_view,
-        ),
-    ));
+    // FINGERS VIEW
+    let label_fg = Label::new("Your enrolled fingers").padding(5.0);
 
-    main_view
-}
+    let info_fg = Label::new("Enrolling a finger again re-issues its protected template with a new key").padding(5.0);
 
+    let fingers_list = List::new(|| {
+        SizedBox::new(
+            Label::new(|enrolled: &EnrolledFinger, _env: &_| enrolled.finger.label().to_string())
+                .with_text_color(TEXT_COLOR)
+                .center()
+                .background(CELL_BG_COLOR)
+        )
+        .width(CELL_WIDTH)
+        .padding(5.0)
+        .border(Color::BLACK, 1.0)
+    })
+    .lens(AppState::fingers);
 
-struct Delegate;
+    let finger_button_fg = finger_selector();
 
-impl druid::AppDelegate<AppState> for Delegate {
-    fn command(
-        &mut self,
-        _ctx: &mut druid::DelegateCtx,
-        _target: druid::Target,
-        cmd: &druid::Command,
-        data: &mut AppState,
-        _env: &druid::Env,
-    ) -> druid::Handled {
-        if cmd.is(SHOW_REGISTER) {
-            data.view = ViewSelector::Register;
-            return druid::Handled::Yes;
-        } else if cmd.is(SHOW_LOGIN) {
-            data.view = ViewSelector::Login;
-            return druid::Handled::Yes;
-        } else if cmd.is(SHOW_CREDENTIALS) {
-            data.view = ViewSelector::Credentials;
-            return druid::Handled::Yes;
-        } else if let Some(credentials) = cmd.get(UPDATE_CREDENTIALS) {
-            println!("Updating credentials");
-            // **Modified**: Update credentials in AppState
-            data.credentials = credentials.clone();
-            return druid::Handled::Yes;
+    let pool_enroll = Arc::clone(&pool);
+    let enroll_button = Button::new("Enrol finger").on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        if let Some(key) = data.vault_key.clone() {
+            data.fingers_message = my_child_enroll_finger(&pool_enroll, &user, data.finger, &key);
         }
-        druid::Handled::No
-    }
-}
-fn my_child_delete(binding: &Arc<SqlitePool>, user: &str, site: Option<&str>, data: &mut AppState) {
-    let result = task::block_in_place (||  {
-
-        let task_result = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap()
-        .block_on(async{    
-            delete_credentials(&binding, &user, &(site.unwrap())).await.expect("Failed to delete credential");
-            data.site = "".to_string();
-            data.site_username = "".to_string();
-            data.site_password = "".to_string();
-        });
+        my_child_fingers(&pool_enroll, &user, data);
     });
-}
 
+    let pool_remove = Arc::clone(&pool);
+    let remove_finger_button = Button::new("Remove finger").on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        data.fingers_message = my_child_remove_finger(&pool_remove, &user, data.finger);
+        my_child_fingers(&pool_remove, &user, data);
+    });
 
+    let message_fg = Label::new(|data: &AppState, _env: &_| data.fingers_message.clone())
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .fix_width(800.0)
+        .padding(5.0);
 
-fn my_child_update(binding: &Arc<SqlitePool>, user: &str, site: Option<&str>, site_username: Option<&str>, site_password: Option<&str>, data: &mut AppState) {
-    let result = task::block_in_place (||  {
+    let back_button_fg = Button::new("Back to credentials").on_click(|_ctx, data: &mut AppState, _env| {
+        data.view = ViewSelector::Credentials;
+    });
 
-        let task_result = tokio::runtime::Builder::new_current_thread()
+    let fingers_view = Flex::column()
+    .with_child(label_fg)
+    .with_child(info_fg)
+    .with_spacer(20.0)
+    .with_child(
+        Flex::row()
+            .with_child(
+                Flex::column()
+                .with_child(finger_button_fg)
+                .with_spacer(20.0)
+                .with_child(enroll_button)
+                .with_spacer(20.0)
+                .with_child(remove_finger_button)
+            )
+            .with_spacer(40.0)
+            .with_child(Scroll::new(fingers_list).vertical().fix_height(250.0))
+    )
+    .with_spacer(10.0)
+    .with_child(message_fg)
+    .with_spacer(40.0)
+    .with_child(back_button_fg);
+
+
+    // RECOVERY VIEW
+    let label_rc = Label::new("Unlock without fingerprint").padding(5.0);
+
+    let username_input_rc = TextBox::new().with_placeholder("Username").lens(AppState::username);
+    let password_input_rc = TextBox::new().with_placeholder("Master password").lens(AppState::master_password);
+    let recovery_input_rc = TextBox::new().with_placeholder("Recovery key").lens(AppState::recovery_key);
+
+    let pool_password = Arc::clone(&pool);
+    let password_button_rc = Button::new("Unlock with master password").on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        let key = my_child_unlock_password(&pool_password, &user, &data.master_password);
+        data.master_password = "".to_string();
+        match key {
+            Some(key) => my_child_open_vault(&pool_password, &user, key, data),
+            None => data.security_message = "Wrong username or master password".to_string(),
+        }
+    });
+
+    let pool_recovery = Arc::clone(&pool);
+    let recovery_button_rc = Button::new("Unlock with recovery key").on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        let key = my_child_unlock_recovery(&pool_recovery, &user, &data.recovery_key);
+        data.recovery_key = "".to_string();
+        match key {
+            Some(key) => my_child_open_vault(&pool_recovery, &user, key, data),
+            None => data.security_message = "Wrong username or recovery key".to_string(),
+        }
+    });
+
+    let message_rc = Label::new(|data: &AppState, _env: &_| data.security_message.clone()).padding(5.0);
+
+    let back_button_rc = Button::new("Back to login").on_click(|_ctx, data: &mut AppState, _env| {
+        data.master_password = "".to_string();
+        data.recovery_key = "".to_string();
+        data.view = ViewSelector::Login;
+    });
+
+    let recovery_view = Flex::column()
+    .with_child(label_rc)
+    .with_spacer(20.0)
+    .with_child(username_input_rc)
+    .with_spacer(20.0)
+    .with_child(password_input_rc)
+    .with_spacer(10.0)
+    .with_child(password_button_rc)
+    .with_spacer(20.0)
+    .with_child(recovery_input_rc)
+    .with_spacer(10.0)
+    .with_child(recovery_button_rc)
+    .with_spacer(10.0)
+    .with_child(message_rc)
+    .with_spacer(20.0)
+    .with_child(back_button_rc);
+
+
+    // SECURITY VIEW
+    let label_sc = Label::new("Master password and recovery key").padding(5.0);
+
+    let password_input_sc = TextBox::new().with_placeholder("New master password").lens(AppState::master_password);
+    let confirm_input_sc = TextBox::new().with_placeholder("Confirm master password").lens(AppState::master_password_confirm);
+
+    let pool_set_password = Arc::clone(&pool);
+    let password_button_sc = Button::new("Set master password").on_click(move |_ctx, data: &mut AppState, _env| {
+        if data.master_password != data.master_password_confirm {
+            data.security_message = "The passwords do not match".to_string();
+            return;
+        }
+        if data.master_password.chars().count() < MIN_MASTER_PASSWORD_LEN {
+            data.security_message = format!("The master password needs at least {} characters", MIN_MASTER_PASSWORD_LEN);
+            return;
+        }
+        let key = match data.vault_key.clone() {
+            Some(key) => key,
+            None => return,
+        };
+        let user = data.username.clone();
+        data.security_message = if my_child_set_master_password(&pool_set_password, &user, &data.master_password, &key) {
+            "Master password set".to_string()
+        } else {
+            "Failed to set the master password".to_string()
+        };
+        data.master_password = "".to_string();
+        data.master_password_confirm = "".to_string();
+    });
+
+    let pool_new_recovery = Arc::clone(&pool);
+    let recovery_button_sc = Button::new("Generate recovery key").on_click(move |_ctx, data: &mut AppState, _env| {
+        let key = match data.vault_key.clone() {
+            Some(key) => key,
+            None => return,
+        };
+        let user = data.username.clone();
+        match my_child_new_recovery_key(&pool_new_recovery, &user, &key) {
+            Some(recovery) => {
+                data.recovery_key = recovery;
+                data.security_message = "Print or write down this recovery key, it will not be shown again.\nAny previous recovery key no longer works.".to_string();
+            }
+            None => data.security_message = "Failed to generate a recovery key".to_string(),
+        }
+    });
+
+    let recovery_label_sc = Label::new(|data: &AppState, _env: &_| data.recovery_key.clone()).padding(5.0);
+    let message_sc = Label::new(|data: &AppState, _env: &_| data.security_message.clone()).padding(5.0);
+
+    let back_button_sc = Button::new("Back to credentials").on_click(|_ctx, data: &mut AppState, _env| {
+        data.master_password = "".to_string();
+        data.master_password_confirm = "".to_string();
+        data.recovery_key = "".to_string();
+        data.view = ViewSelector::Credentials;
+    });
+
+    let security_view = Flex::column()
+    .with_child(label_sc)
+    .with_spacer(20.0)
+    .with_child(password_input_sc)
+    .with_spacer(10.0)
+    .with_child(confirm_input_sc)
+    .with_spacer(10.0)
+    .with_child(password_button_sc)
+    .with_spacer(30.0)
+    .with_child(recovery_button_sc)
+    .with_spacer(10.0)
+    .with_child(recovery_label_sc)
+    .with_spacer(10.0)
+    .with_child(message_sc)
+    .with_spacer(20.0)
+    .with_child(back_button_sc);
+
+
+    // HISTORY VIEW
+    let label_hs = Label::new(|data: &AppState, _env: &_| format!("Previous passwords for {}", data.site)).padding(5.0);
+
+    let info_hs = Label::new("Restoring a password loads it into the form, save the changes to apply it").padding(5.0);
+
+    let history_list = List::new(|| {
+        Flex::row()
+            .with_child(
+                SizedBox::new(
+                    Label::new(|entry: &PasswordHistory, _env: &_| format!("{} UTC", entry.changed_at))
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|entry: &PasswordHistory, _env: &_| entry.site_password.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                Button::new("Restore").on_click(|ctx, entry: &mut PasswordHistory, _env| {
+                    ctx.submit_command(DruidCommand::new(RESTORE_PASSWORD, entry.site_password.clone(), Target::Global));
+                })
+                .padding(5.0),
+            )
+    })
+    .lens(AppState::history);
+
+    let back_button_hs = Button::new("Back to credentials").on_click(|_ctx, data: &mut AppState, _env| {
+        data.history = Arc::new(Vec::new());
+        data.view = ViewSelector::Credentials;
+    });
+
+    let history_view = Flex::column()
+    .with_child(label_hs)
+    .with_child(info_hs)
+    .with_spacer(20.0)
+    .with_child(Scroll::new(history_list).vertical().fix_height(250.0))
+    .with_spacer(20.0)
+    .with_child(back_button_hs);
+
+
+    // BREACHES VIEW
+    let label_br = Label::new("Breached passwords").padding(5.0);
+
+    let info_br = Label::new(|data: &AppState, _env: &_| data.breach_message.clone()).padding(5.0);
+
+    let breaches_list = List::new(|| {
+        Flex::row()
+            .with_child(
+                SizedBox::new(
+                    Label::new(|cred: &Credential, _env: &_| cred.site.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|cred: &Credential, _env: &_| cred.site_username.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|cred: &Credential, _env: &_| format!("Seen {} times", cred.breaches))
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                Button::new("Change password").on_click(|ctx, cred: &mut Credential, _env| {
+                    ctx.submit_command(DruidCommand::new(EDIT_CREDENTIAL, cred.id, Target::Global));
+                })
+                .padding(5.0),
+            )
+    })
+    //Read only, the rows only submit commands
+    .lens(druid::lens::Map::new(
+        |data: &AppState| -> Arc<Vec<Credential>> {
+            Arc::new(data.credentials.iter().filter(|cred| cred.breaches > 0).cloned().collect())
+        },
+        |_data: &mut AppState, _credentials: Arc<Vec<Credential>>| {},
+    ));
+
+    let check_button_br = Button::new("Check again").on_click(|_ctx, data: &mut AppState, _env| {
+        check_breaches(data);
+    });
+
+    let back_button_br = Button::new("Back to credentials").on_click(|_ctx, data: &mut AppState, _env| {
+        data.view = ViewSelector::Credentials;
+    });
+
+    let breaches_view = Flex::column()
+    .with_child(label_br)
+    .with_child(info_br)
+    .with_spacer(20.0)
+    .with_child(Scroll::new(breaches_list).vertical().fix_height(300.0))
+    .with_spacer(20.0)
+    .with_child(Flex::row().with_child(check_button_br).with_spacer(20.0).with_child(back_button_br));
+
+
+    // HEALTH VIEW
+    let label_hl = Label::new("Vault health").padding(5.0);
+
+    let info_hl = Label::new(|data: &AppState, _env: &_| data.health_message.clone()).padding(5.0);
+
+    let health_list = List::new(|| {
+        Flex::row()
+            .with_child(
+                SizedBox::new(
+                    Label::new(|issue: &HealthIssue, _env: &_| issue.issue.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(STRENGTH_CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|issue: &HealthIssue, _env: &_| issue.site.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|issue: &HealthIssue, _env: &_| issue.site_username.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|issue: &HealthIssue, _env: &_| issue.detail.clone())
+                        .with_line_break_mode(LineBreaking::WordWrap)
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH * 2.0)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                Button::new("Change password").on_click(|ctx, issue: &mut HealthIssue, _env| {
+                    ctx.submit_command(DruidCommand::new(EDIT_CREDENTIAL, issue.id, Target::Global));
+                })
+                .padding(5.0),
+            )
+    })
+    .lens(AppState::health);
+
+    let pool_export_health = Arc::clone(&pool);
+    let export_button_hl = Button::new("Export JSON").on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        if let Some(report) = my_child_health(&pool_export_health, &user, data) {
+            let path = format!("health_report_{}.json", user);
+            let written = report
+                .to_json()
+                .map_err(|e| e.to_string())
+                .and_then(|json| std::fs::write(&path, json).map_err(|e| e.to_string()));
+            data.health_message = match written {
+                Ok(()) => format!("{}\nExported to {}", report.summary(), path),
+                Err(e) => format!("Failed to export the health report: {}", e),
+            };
+        }
+    });
+
+    let back_button_hl = Button::new("Back to credentials").on_click(|_ctx, data: &mut AppState, _env| {
+        data.view = ViewSelector::Credentials;
+    });
+
+    let health_view = Flex::column()
+    .with_child(label_hl)
+    .with_child(info_hl)
+    .with_spacer(20.0)
+    .with_child(Scroll::new(health_list).vertical().fix_height(350.0))
+    .with_spacer(20.0)
+    .with_child(Flex::row().with_child(export_button_hl).with_spacer(20.0).with_child(back_button_hl));
+
+
+    // IMPORT VIEW
+    let label_im = Label::new("Import and export credentials").padding(5.0);
+
+    let info_im = Label::new("CSV exports of Chrome, Edge, Firefox, Bitwarden and LastPass are recognised from their columns, Bitwarden .json and 1Password .1pux exports from their extension, KeePass files need their password").padding(5.0);
+
+    let path_input_im = TextBox::new().with_placeholder("Path of the file").fix_width(400.0).lens(AppState::import_path);
+
+    let kdbx_password_input_im = TextBox::new().with_placeholder("KeePass password").fix_width(200.0).lens(AppState::kdbx_password);
+
+    let kdbx_confirm_input_im = TextBox::new().with_placeholder("Confirm to export").fix_width(200.0).lens(AppState::kdbx_password_confirm);
+
+    let preview_button_im = Button::new("Preview").on_click(|_ctx, data: &mut AppState, _env| {
+        let path = data.import_path.trim().to_string();
+        let extension = path.to_lowercase();
+        let import = if extension.ends_with(".kdbx") {
+            std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e)).and_then(|input| kdbx::import(&input, &data.kdbx_password))
+        } else if extension.ends_with(".1pux") {
+            std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e)).and_then(|input| importer::parse_1pux(&input))
+        } else if extension.ends_with(".json") {
+            std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e)).and_then(|input| importer::parse_bitwarden_json(&input))
+        } else {
+            std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e)).and_then(|input| importer::parse_csv(&input))
+        };
+        match import {
+            Ok(import) => show_import_preview(import, data),
+            Err(e) => {
+                data.import_rows = Arc::new(Vec::new());
+                data.import_items = Arc::new(Vec::new());
+                data.import_message = e;
+            }
+        }
+    });
+
+    let message_im = Label::new(|data: &AppState, _env: &_| data.import_message.clone())
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .fix_width(800.0)
+        .padding(5.0);
+
+    let import_list = List::new(|| {
+        Flex::row()
+            .with_child(
+                SizedBox::new(
+                    Label::new(|row: &ImportRow, _env: &_| format!("Line {}", row.line))
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(STRENGTH_CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|row: &ImportRow, _env: &_| row.credential.site.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|row: &ImportRow, _env: &_| row.credential.site_username.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|row: &ImportRow, _env: &_| row.credential.url.clone())
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(CELL_WIDTH * 2.0)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+            .with_child(
+                SizedBox::new(
+                    Label::new(|row: &ImportRow, _env: &_| if row.duplicate { "Duplicate".to_string() } else { "New".to_string() })
+                        .with_text_color(TEXT_COLOR)
+                        .center()
+                        .background(CELL_BG_COLOR)
+                )
+                .width(STRENGTH_CELL_WIDTH)
+                .padding(5.0)
+                .border(Color::BLACK, 1.0),
+            )
+    })
+    .lens(AppState::import_rows);
+
+    let pool_import = Arc::clone(&pool);
+    let import_button_im = Button::dynamic(|data: &AppState, _env| {
+        format!(
+            "Import {} new credentials and {} secure notes",
+            data.import_rows.iter().filter(|row| !row.duplicate).count(),
+            data.import_items.len()
+        )
+    }).on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        my_child_import(&pool_import, &user, data);
+    });
+
+    //Never overwrites a file, a KeePass backup is not replaced by mistake
+    let export_button_im = Button::new("Export to KeePass").on_click(|_ctx, data: &mut AppState, _env| {
+        let path = data.import_path.trim().to_string();
+        data.import_message = if !path.to_lowercase().ends_with(".kdbx") {
+            "Choose a path ending with .kdbx".to_string()
+        } else if Path::new(&path).exists() {
+            format!("{} already exists, choose another path", path)
+        } else if data.kdbx_password.chars().count() < MIN_MASTER_PASSWORD_LEN {
+            format!("The KeePass password needs at least {} characters", MIN_MASTER_PASSWORD_LEN)
+        } else if data.kdbx_password != data.kdbx_password_confirm {
+            "The KeePass passwords do not match".to_string()
+        } else {
+            match kdbx::export(&data.credentials, &data.kdbx_password).and_then(|file| std::fs::write(&path, file).map_err(|e| e.to_string())) {
+                Ok(()) => {
+                    data.kdbx_password_confirm = "".to_string();
+                    format!("Exported {} credentials to {}", data.credentials.len(), path)
+                }
+                Err(e) => format!("Failed to export to {}: {}", path, e),
+            }
+        };
+    });
+
+    let back_button_im = Button::new("Back to credentials").on_click(|_ctx, data: &mut AppState, _env| {
+        data.import_rows = Arc::new(Vec::new());
+        data.import_items = Arc::new(Vec::new());
+        data.kdbx_password = "".to_string();
+        data.kdbx_password_confirm = "".to_string();
+        data.view = ViewSelector::Credentials;
+    });
+
+    let import_view = Flex::column()
+    .with_child(label_im)
+    .with_child(info_im)
+    .with_spacer(20.0)
+    .with_child(
+        Flex::row()
+        .with_child(path_input_im)
+        .with_spacer(10.0)
+        .with_child(kdbx_password_input_im)
+        .with_spacer(10.0)
+        .with_child(kdbx_confirm_input_im)
+        .with_spacer(10.0)
+        .with_child(preview_button_im)
+        .with_spacer(10.0)
+        .with_child(export_button_im)
+    )
+    .with_spacer(10.0)
+    .with_child(message_im)
+    .with_spacer(10.0)
+    .with_child(Scroll::new(import_list).vertical().fix_height(300.0))
+    .with_spacer(20.0)
+    .with_child(Flex::row().with_child(import_button_im).with_spacer(20.0).with_child(back_button_im));
+
+
+    // BACKUP VIEW
+    let label_bk = Label::new("Encrypted backup of the vault").padding(5.0);
+
+    let info_bk = Label::new("The backup holds your credentials, their previous passwords and your items. It is protected by a backup password or by your recovery key, so it can be restored without your fingerprint.")
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .fix_width(800.0)
+        .padding(5.0);
+
+    let path_input_bk = TextBox::new().with_placeholder("Path of the backup file").fix_width(400.0).lens(AppState::backup_path);
+    let password_input_bk = TextBox::new().with_placeholder("Backup password").fix_width(200.0).lens(AppState::backup_password);
+    let confirm_input_bk = TextBox::new().with_placeholder("Confirm backup password").fix_width(200.0).lens(AppState::backup_password_confirm);
+    let recovery_input_bk = TextBox::new().with_placeholder("or recovery key").fix_width(300.0).lens(AppState::backup_recovery_key);
+
+    //Never overwrites a file, an older backup is not replaced by mistake
+    let pool_backup = Arc::clone(&pool);
+    let export_button_bk = Button::new("Export backup").on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        let path = data.backup_path.trim().to_string();
+        let checked = if path.is_empty() {
+            Err("Choose the path of the backup file".to_string())
+        } else if Path::new(&path).exists() {
+            Err(format!("{} already exists, choose another path", path))
+        } else {
+            data.backup_secret().and_then(|secret| match secret {
+                backup::Secret::Password(password) if password != data.backup_password_confirm => Err("The passwords do not match".to_string()),
+                backup::Secret::Password(password) if password.chars().count() < MIN_MASTER_PASSWORD_LEN => {
+                    Err(format!("The backup password needs at least {} characters", MIN_MASTER_PASSWORD_LEN))
+                }
+                //A mistyped recovery key would make the backup unreadable
+                backup::Secret::RecoveryKey(recovery_key) => match (my_child_unlock_recovery(&pool_backup, &user, recovery_key), &data.vault_key) {
+                    (Some(key), Some(vault_key)) if key.as_bytes() == vault_key.as_bytes() => Ok(()),
+                    _ => Err("This is not the current recovery key of your vault".to_string()),
+                },
+                _ => Ok(()),
+            })
+        };
+        data.backup_message = match checked.and_then(|()| my_child_backup(&pool_backup, &user, data)) {
+            Ok(content) => {
+                let written = data
+                    .backup_secret()
+                    .and_then(|secret| backup::seal(&content, secret))
+                    .and_then(|file| std::fs::write(&path, file).map_err(|e| e.to_string()));
+                match written {
+                    Ok(()) => format!("{}\nSaved to {}", content.summary(), path),
+                    Err(e) => format!("Failed to write the backup to {}: {}", path, e),
+                }
+            }
+            Err(e) => e,
+        };
+        data.clear_backup_secrets();
+    });
+
+    let replace_input_bk = Checkbox::new("Replace: delete the credentials and items of the vault before restoring").lens(AppState::backup_replace);
+
+    let pool_restore = Arc::clone(&pool);
+    let restore_button_bk = Button::dynamic(|data: &AppState, _env| {
+        if data.backup_replace { "Replace the vault with the backup".to_string() } else { "Merge the backup into the vault".to_string() }
+    }).on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        let path = data.backup_path.trim().to_string();
+        let opened = std::fs::read(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path, e))
+            .and_then(|file| data.backup_secret().and_then(|secret| backup::open(&file, secret)));
+        data.clear_backup_secrets();
+        match opened {
+            Ok(content) => {
+                let mode = if data.backup_replace { backup::RestoreMode::Replace } else { backup::RestoreMode::Merge };
+                my_child_restore(&pool_restore, &user, &content, mode, data);
+            }
+            Err(e) => data.backup_message = e,
+        }
+        data.backup_replace = false;
+    });
+
+    let message_bk = Label::new(|data: &AppState, _env: &_| data.backup_message.clone())
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .fix_width(800.0)
+        .padding(5.0);
+
+    let back_button_bk = Button::new("Back to credentials").on_click(|_ctx, data: &mut AppState, _env| {
+        data.clear_backup_secrets();
+        data.backup_replace = false;
+        data.view = ViewSelector::Credentials;
+    });
+
+    let backup_view = Flex::column()
+    .with_child(label_bk)
+    .with_child(info_bk)
+    .with_spacer(20.0)
+    .with_child(path_input_bk)
+    .with_spacer(10.0)
+    .with_child(
+        Flex::row()
+        .with_child(password_input_bk)
+        .with_spacer(10.0)
+        .with_child(confirm_input_bk)
+        .with_spacer(10.0)
+        .with_child(recovery_input_bk)
+    )
+    .with_spacer(10.0)
+    .with_child(export_button_bk)
+    .with_spacer(20.0)
+    .with_child(replace_input_bk)
+    .with_spacer(10.0)
+    .with_child(restore_button_bk)
+    .with_spacer(10.0)
+    .with_child(message_bk)
+    .with_spacer(20.0)
+    .with_child(back_button_bk);
+
+
+    // EXPORT VIEW
+    let label_ex = Label::new("Plaintext export").padding(5.0);
+
+    let info_ex = Label::new("Writes your credentials and items UNENCRYPTED, to move them to another password manager. Anyone who can read the file gets every password: import it right away, then delete it. Use the encrypted backup to keep a copy.")
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .fix_width(800.0)
+        .padding(5.0);
+
+    let layout_button_ex = Button::dynamic(|data: &AppState, _env| format!("Layout: {}", data.export_layout.label()))
+        .on_click(|_ctx, data: &mut AppState, _env| {
+            data.export_layout = data.export_layout.next();
+        });
+
+    let path_input_ex = TextBox::new().with_placeholder("Path of the file").fix_width(400.0).lens(AppState::export_path);
+
+    //The released key has to be the key of the open vault, not only any enrolled finger
+    let pool_verify = Arc::clone(&pool);
+    let verify_button_ex = Button::new("Verify fingerprint").on_click(move |_ctx, data: &mut AppState, _env| {
+        let user = data.username.clone();
+        let verified = match (my_child_login(user, Arc::clone(&pool_verify)), &data.vault_key) {
+            (Ok(key), Some(vault_key)) => key.as_bytes() == vault_key.as_bytes(),
+            _ => false,
+        };
+        if verified {
+            data.export_verified_at = Some(unix_time());
+            data.export_message = format!("Fingerprint verified, export within {} seconds", EXPORT_VERIFICATION_SECONDS);
+        } else {
+            data.export_verified_at = None;
+            data.export_message = "The fingerprint was not verified".to_string();
+        }
+    });
+
+    let confirm_input_ex = Checkbox::new("I understand the file is not encrypted and holds all my passwords").lens(AppState::export_confirmed);
+
+    //Never overwrites a file, one verification and confirmation per file
+    let export_button_ex = Button::new("Export unencrypted file").on_click(|_ctx, data: &mut AppState, _env| {
+        let path = data.export_path.trim().to_string();
+        let extension = format!(".{}", data.export_layout.extension());
+        let fresh = data.export_verified_at.map_or(false, |time| unix_time().saturating_sub(time) <= EXPORT_VERIFICATION_SECONDS);
+        data.export_message = if !fresh {
+            format!("Verify your fingerprint first, a verification is valid for {} seconds", EXPORT_VERIFICATION_SECONDS)
+        } else if !data.export_confirmed {
+            "Tick the confirmation first".to_string()
+        } else if !path.to_lowercase().ends_with(&extension) {
+            format!("Choose a path ending with {}", extension)
+        } else if Path::new(&path).exists() {
+            format!("{} already exists, choose another path", path)
+        } else {
+            let written = exporter::export(data.export_layout, &data.credentials, &data.items)
+                .and_then(|export| write_new_private_file(&path, &export.data).map(|()| export).map_err(|e| e.to_string()));
+            data.export_verified_at = None;
+            data.export_confirmed = false;
+            match written {
+                Ok(export) => format!("Exported {} to {}\nDelete the file once it is imported", export.summary(), path),
+                Err(e) => format!("Failed to export to {}: {}", path, e),
+            }
+        };
+    });
+
+    let message_ex = Label::new(|data: &AppState, _env: &_| data.export_message.clone())
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .fix_width(800.0)
+        .padding(5.0);
+
+    let back_button_ex = Button::new("Back to credentials").on_click(|_ctx, data: &mut AppState, _env| {
+        data.export_verified_at = None;
+        data.export_confirmed = false;
+        data.view = ViewSelector::Credentials;
+    });
+
+    let export_view = Flex::column()
+    .with_child(label_ex)
+    .with_child(info_ex)
+    .with_spacer(20.0)
+    .with_child(
+        Flex::row()
+        .with_child(layout_button_ex)
+        .with_spacer(10.0)
+        .with_child(path_input_ex)
+    )
+    .with_spacer(10.0)
+    .with_child(verify_button_ex)
+    .with_spacer(10.0)
+    .with_child(confirm_input_ex)
+    .with_spacer(10.0)
+    .with_child(export_button_ex)
+    .with_spacer(10.0)
+    .with_child(message_ex)
+    .with_spacer(20.0)
+    .with_child(back_button_ex);
+
+
+    // MAIN VIEW
+    let main_view = Flex::column()
+    .with_child(view_for(ViewSelector::Login, login_view))
+    .with_child(view_for(ViewSelector::Register, register_view))
+    .with_child(view_for(ViewSelector::Credentials, credentials_view))
+    .with_child(view_for(ViewSelector::Fingers, fingers_view))
+    .with_child(view_for(ViewSelector::Recovery, recovery_view))
+    .with_child(view_for(ViewSelector::Security, security_view))
+    .with_child(view_for(ViewSelector::History, history_view))
+    .with_child(view_for(ViewSelector::Breaches, breaches_view))
+    .with_child(view_for(ViewSelector::Health, health_view))
+    .with_child(view_for(ViewSelector::Import, import_view))
+    .with_child(view_for(ViewSelector::Backup, backup_view))
+    .with_child(view_for(ViewSelector::Export, export_view));
+
+    main_view.controller(PasswordCheck { dataset: None }).controller(OtpTicker { timer: TimerToken::INVALID })
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+//Recomputes the one-time codes shown in the credentials table
+fn refresh_otp_codes(data: &mut AppState) {
+    if data.credentials.iter().all(|cred| cred.otp.is_empty()) {
+        return;
+    }
+    let now = unix_time();
+    let credentials = data.credentials.iter().cloned().map(|mut cred| {
+        cred.otp_code = totp::parse(&cred.otp).map(|otp| otp.display(now)).unwrap_or_default();
+        cred
+    }).collect();
+    data.credentials = Arc::new(credentials);
+}
+
+//Flags the stored passwords found in the offline breach dataset, see breach.rs
+fn check_breaches(data: &mut AppState) {
+    let path = match hibp_path() {
+        Some(path) => path,
+        None => {
+            data.breach_message = "No breach dataset, set HIBP_PATH to the downloaded Have I Been Pwned file or range directory".to_string();
+            return;
+        }
+    };
+    let mut dataset = match breach::Dataset::open(&path) {
+        Ok(dataset) => dataset,
+        Err(e) => {
+            data.breach_message = format!("Failed to open the breach dataset {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let mut failed = 0;
+    let mut credentials = Vec::new();
+    for cred in data.credentials.iter() {
+        let mut cred = cred.clone();
+        cred.breaches = match dataset.count(&cred.site_password) {
+            Ok(count) => count,
+            Err(e) => {
+                eprintln!("Failed to check the password of {}: {}", cred.site, e);
+                failed += 1;
+                0
+            }
+        };
+        credentials.push(cred);
+    }
+
+    let compromised = credentials.iter().filter(|cred| cred.breaches > 0).count();
+    data.breach_message = format!("{} of {} passwords found in breaches", compromised, credentials.len());
+    if failed > 0 {
+        data.breach_message.push_str(&format!(", {} could not be checked", failed));
+    }
+    data.credentials = Arc::new(credentials);
+}
+
+//Estimates the password of the form and counts it in the breach dataset once when it changes
+//(typed, generated, loaded for editing), not on every repaint. The dataset is opened on first
+//use and kept open. Changes are seen in update, where the data is read only, so the checks run
+//on a command sent to this widget
+struct PasswordCheck {
+    dataset: Option<breach::Dataset>,
+}
+
+impl PasswordCheck {
+    //None when no dataset is configured or readable
+    fn count(&mut self, password: &str) -> Option<u64> {
+        if password.is_empty() {
+            return None;
+        }
+        if self.dataset.is_none() {
+            self.dataset = breach::Dataset::open(&hibp_path()?).ok();
+        }
+        self.dataset.as_mut()?.count(password).ok()
+    }
+}
+
+impl<W: Widget<AppState>> Controller<AppState, W> for PasswordCheck {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event, data: &mut AppState, env: &Env) {
+        match event {
+            Event::Command(cmd) if cmd.is(CHECK_PASSWORD) => {
+                data.password_strength = if data.site_password.is_empty() { "".to_string() } else { strength::estimate(&data.site_password).summary() };
+                data.password_breaches = self.count(&data.site_password);
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+
+    fn update(&mut self, child: &mut W, ctx: &mut UpdateCtx, old_data: &AppState, data: &AppState, env: &Env) {
+        if old_data.site_password != data.site_password {
+            ctx.submit_command(CHECK_PASSWORD.to(ctx.widget_id()));
+        }
+        child.update(ctx, old_data, data, env)
+    }
+}
+
+//Ticks every second to keep the one-time codes and their countdown current
+struct OtpTicker {
+    timer: TimerToken,
+}
+
+impl<W: Widget<AppState>> Controller<AppState, W> for OtpTicker {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event, data: &mut AppState, env: &Env) {
+        match event {
+            Event::WindowConnected => self.timer = ctx.request_timer(Duration::from_secs(1)),
+            Event::Timer(token) if *token == self.timer => {
+                if data.view == ViewSelector::Credentials {
+                    refresh_otp_codes(data);
+                }
+                self.timer = ctx.request_timer(Duration::from_secs(1));
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+//Only displays the widget while the given view is selected
+fn view_for(view: ViewSelector, widget: impl Widget<AppState> + 'static) -> impl Widget<AppState> {
+    druid::widget::Either::new(
+        move |data: &AppState, _env| data.view == view,
+        widget,
+        SizedBox::empty(),
+    )
+}
+
+//Only displays the widget while the condition holds, with the spacing of the column around it
+fn view_if(condition: impl Fn(&AppState) -> bool + 'static, widget: impl Widget<AppState> + 'static) -> impl Widget<AppState> {
+    druid::widget::Either::new(
+        move |data: &AppState, _env| condition(data),
+        widget.padding((0.0, 0.0, 0.0, 20.0)),
+        SizedBox::empty(),
+    )
+}
+
+//Options of the password generator, the length and character classes only apply to the random and pronounceable modes
+fn generator_panel() -> impl Widget<GeneratorSettings> {
+    let mode_button = Button::dynamic(|settings: &GeneratorSettings, _env| format!("Mode: {}", settings.mode.label()))
+        .on_click(|_ctx, settings: &mut GeneratorSettings, _env| {
+            settings.mode = settings.mode.next();
+        });
+
+    let length_row = Flex::row()
+        .with_child(Label::new(|settings: &GeneratorSettings, _env: &_| format!("Length: {}", settings.length.round())))
+        .with_spacer(10.0)
+        .with_child(
+            Slider::new()
+                .with_range(generator::MIN_LENGTH as f64, generator::MAX_LENGTH as f64)
+                .lens(GeneratorSettings::length),
+        );
+
+    let classes = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Checkbox::new("a-z").lens(GeneratorSettings::lowercase))
+                .with_spacer(10.0)
+                .with_child(Checkbox::new("A-Z").lens(GeneratorSettings::uppercase))
+                .with_spacer(10.0)
+                .with_child(Checkbox::new("0-9").lens(GeneratorSettings::digits))
+                .with_spacer(10.0)
+                .with_child(Checkbox::new("!#$").lens(GeneratorSettings::symbols)),
+        )
+        .with_spacer(5.0)
+        .with_child(Checkbox::new("Exclude ambiguous characters").lens(GeneratorSettings::exclude_ambiguous));
+
+    let words_row = Flex::row()
+        .with_child(Label::new(|settings: &GeneratorSettings, _env: &_| format!("Words: {}", settings.words.round())))
+        .with_spacer(10.0)
+        .with_child(
+            Slider::new()
+                .with_range(generator::MIN_WORDS as f64, generator::MAX_WORDS as f64)
+                .lens(GeneratorSettings::words),
+        );
+
+    let options = druid::widget::Either::new(
+        |settings: &GeneratorSettings, _env| settings.mode == GeneratorMode::Diceware,
+        words_row,
+        Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(length_row)
+            .with_spacer(5.0)
+            .with_child(druid::widget::Either::new(
+                |settings: &GeneratorSettings, _env| settings.mode == GeneratorMode::Random,
+                classes,
+                SizedBox::empty(),
+            )),
+    );
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(mode_button)
+        .with_spacer(5.0)
+        .with_child(options)
+}
+
+//Button cycling through the finger positions
+fn finger_selector() -> impl Widget<AppState> {
+    Button::dynamic(|data: &AppState, _env| format!("Finger: {}", data.finger.label()))
+        .on_click(|_ctx, data: &mut AppState, _env| {
+            data.finger = data.finger.next();
+        })
+}
+
+
+struct Delegate {
+    pool: Arc<SqlitePool>,
+}
+
+impl druid::AppDelegate<AppState> for Delegate {
+    fn command(
+        &mut self,
+        _ctx: &mut druid::DelegateCtx,
+        _target: druid::Target,
+        cmd: &druid::Command,
+        data: &mut AppState,
+        _env: &druid::Env,
+    ) -> druid::Handled {
+        if cmd.is(SHOW_REGISTER) {
+            data.view = ViewSelector::Register;
+            return druid::Handled::Yes;
+        } else if cmd.is(SHOW_LOGIN) {
+            data.view = ViewSelector::Login;
+            return druid::Handled::Yes;
+        } else if cmd.is(SHOW_CREDENTIALS) {
+            data.view = ViewSelector::Credentials;
+            return druid::Handled::Yes;
+        } else if let Some(id) = cmd.get(EDIT_CREDENTIAL) {
+            if let Some(cred) = data.credentials.iter().find(|cred| cred.id == *id).cloned() {
+                data.load_form(cred);
+            }
+            //Also sent from the breach report
+            data.view = ViewSelector::Credentials;
+            return druid::Handled::Yes;
+        } else if let Some(id) = cmd.get(EDIT_ITEM) {
+            if let Some(item) = data.items.iter().find(|item| item.id == *id).cloned() {
+                data.category = Some(item.content.kind());
+                data.editing_item = Some(item.id);
+                data.item_title = item.title;
+                data.item_fields = Arc::new(item.content.fields());
+            }
+            return druid::Handled::Yes;
+        } else if let Some(id) = cmd.get(DELETE_ITEM) {
+            let user = data.username.clone();
+            my_child_delete_item(&self.pool, &user, *id, data);
+            return druid::Handled::Yes;
+        } else if let Some(id) = cmd.get(COPY_CODE) {
+            let cred = data.credentials.iter().find(|cred| cred.id == *id).cloned();
+            if let Some(otp) = cred.and_then(|cred| totp::parse(&cred.otp)) {
+                Application::global().clipboard().put_string(otp.code(unix_time()));
+                //A HOTP code is only valid once, the next one is shown
+                if let totp::Kind::Hotp { .. } = otp.kind {
+                    let user = data.username.clone();
+                    my_child_advance_otp(&self.pool, &user, *id, &otp.next().to_uri(), data);
+                }
+            }
+            return druid::Handled::Yes;
+        } else if let Some(id) = cmd.get(COPY_PASSWORD) {
+            if let Some(cred) = data.credentials.iter().find(|cred| cred.id == *id).cloned() {
+                Application::global().clipboard().put_string(&cred.site_password);
+                let user = data.username.clone();
+                my_child_touch(&self.pool, &user, cred.id, data);
+            }
+            return druid::Handled::Yes;
+        } else if let Some(id) = cmd.get(SELECT_CREDENTIAL) {
+            //Clicking the selected row again clears the selection
+            data.selected = if data.selected == Some(*id) { None } else { Some(*id) };
+            return druid::Handled::Yes;
+        } else if let Some(password) = cmd.get(RESTORE_PASSWORD) {
+            data.site_password = password.clone();
+            data.history = Arc::new(Vec::new());
+            data.view = ViewSelector::Credentials;
+            return druid::Handled::Yes;
+        } else if let Some(credentials) = cmd.get(UPDATE_CREDENTIALS) {
+            println!("Updating credentials");
+            // **Modified**: Update credentials in AppState
+            data.credentials = credentials.clone();
+            return druid::Handled::Yes;
+        }
+        druid::Handled::No
+    }
+}
+fn my_child_delete(binding: &Arc<SqlitePool>, user: &str, id: Option<i32>, data: &mut AppState) {
+    let id = match id {
+        Some(id) => id,
+        None => {
+            data.status_message = "Please select a credential to delete".to_string();
+            return;
+        }
+    };
+    let result = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{    
+            delete_credentials(&binding, &user, id).await
+        })
+    });
+    match result {
+        Ok(_) => {
+            data.status_message = "Credential deleted".to_string();
+            data.selected = None;
+            if data.editing == Some(id) {
+                data.clear_form();
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to delete credential: {}", e);
+            data.status_message = "Failed to delete the credential".to_string();
+        }
+    }
+}
+
+
+
+//Returns whether the credential was updated
+fn my_child_edit(binding: &Arc<SqlitePool>, user: &str, credential: &Credential, data: &mut AppState) -> bool {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => {
+            data.status_message = "Vault is locked".to_string();
+            return false;
+        }
+    };
+    if credential.site == "" || credential.site_username == "" || credential.site_password == "" {
+        data.status_message = "Please fill in the site, username and password".to_string();
+        return false;
+    }
+    let updated = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            update_credentials(binding, &key, user, credential, history_retention()).await
+        })
+    });
+    data.status_message = match &updated {
+        Ok(()) => format!("{} updated", credential.site),
+        Err(sqlx::Error::RowNotFound) => format!("{} was deleted in the meantime, nothing was saved", credential.site),
+        Err(e) => {
+            eprintln!("Failed to update credential: {}", e);
+            "Failed to update the credential, the changes were kept in the form".to_string()
+        }
+    };
+    my_child_update(binding, user, None, data);
+    updated.is_ok()
+}
+
+fn my_child_advance_otp(binding: &Arc<SqlitePool>, user: &str, id: i32, otp: &str, data: &mut AppState) {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => return,
+    };
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            if let Err(e) = update_otp(binding, &key, user, id, otp).await {
+                eprintln!("Failed to update the HOTP counter: {}", e);
+                data.status_message = "Failed to save the HOTP counter, the next code may repeat this one".to_string();
+            }
+        });
+    });
+    my_child_update(binding, user, None, data);
+}
+
+fn my_child_touch(binding: &Arc<SqlitePool>, user: &str, id: i32, data: &mut AppState) {
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            if let Err(e) = touch_credentials(binding, user, id).await {
+                eprintln!("Failed to update the last use: {}", e);
+                data.status_message = "Failed to record the last use of the credential".to_string();
+            }
+        });
+    });
+    my_child_update(binding, user, None, data);
+}
+
+//Lines of the skipped summary shown under the preview, the rest is only counted
+const IMPORT_NOTES_SHOWN: usize = 10;
+
+//Marks the duplicates of the stored credentials and of earlier lines, nothing is saved yet
+fn show_import_preview(import: importer::Import, data: &mut AppState) {
+    let mut known: Vec<Credential> = data.credentials.iter().cloned().collect();
+    let mut rows = Vec::new();
+    for entry in import.entries.iter() {
+        let duplicate = importer::is_duplicate(&entry.credential, &known);
+        if !duplicate {
+            known.push(entry.credential.clone());
+        }
+        rows.push(ImportRow { line: entry.line, credential: entry.credential.clone(), duplicate });
+    }
+
+    //Secure notes are matched by title against the notes of the vault
+    let mut titles: Vec<String> = data
+        .items
+        .iter()
+        .filter(|item| item.content.kind() == ItemKind::SecureNote)
+        .map(|item| item.title.to_lowercase())
+        .collect();
+    let mut items = Vec::new();
+    let mut duplicates = 0;
+    for entry in import.items.iter() {
+        let title = entry.item.title.to_lowercase();
+        if titles.contains(&title) {
+            duplicates += 1;
+            continue;
+        }
+        titles.push(title);
+        items.push(entry.item.clone());
+    }
+
+    let duplicates = duplicates + rows.iter().filter(|row| row.duplicate).count();
+    let mut message = format!("{}, {} already in the vault", import.summary(), duplicates);
+    let notes: Vec<String> = import
+        .skipped
+        .iter()
+        .map(|note| format!("Line {} skipped: {}", note.line, note.message))
+        .chain(import.warnings.iter().map(|note| format!("Line {}: {}", note.line, note.message)))
+        .collect();
+    for note in notes.iter().take(IMPORT_NOTES_SHOWN) {
+        message.push('\n');
+        message.push_str(note);
+    }
+    if notes.len() > IMPORT_NOTES_SHOWN {
+        message.push_str(&format!("\n... and {} more", notes.len() - IMPORT_NOTES_SHOWN));
+    }
+    data.import_rows = Arc::new(rows);
+    data.import_items = Arc::new(items);
+    data.import_message = message;
+}
+
+//Saves the previewed credentials and secure notes that are not duplicates
+fn my_child_import(binding: &Arc<SqlitePool>, user: &str, data: &mut AppState) {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => return,
+    };
+    let rows = Arc::clone(&data.import_rows);
+    let items = Arc::clone(&data.import_items);
+    let (saved, saved_items, failed) = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .unwrap()
         .block_on(async{
-            
-            if site.unwrap_or("") == "" || site_username.unwrap_or("") == "" || site_password.unwrap_or("") == "" {
-                println!("Just updated, Please fill in all fields");
-                match get_credentials(&binding, &user).await {
-                    Ok(creds) => {
-                        data.credentials = Arc::new(creds);
+            let mut saved = 0;
+            let mut failed = 0;
+            for row in rows.iter().filter(|row| !row.duplicate) {
+                match save_credentials(binding, &key, user, &row.credential).await {
+                    Ok(_) => saved += 1,
+                    Err(e) => {
+                        eprintln!("Failed to import line {}: {}", row.line, e);
+                        failed += 1;
                     }
+                }
+            }
+            let mut saved_items = 0;
+            for item in items.iter() {
+                match save_item(binding, &key, user, item).await {
+                    Ok(_) => saved_items += 1,
                     Err(e) => {
-                        eprintln!("Failed to get credentials: {}", e);
+                        eprintln!("Failed to import {}: {}", item.title, e);
+                        failed += 1;
                     }
-                };
-                return;
-            }
-            match save_credentials(&binding, &user, &(site.unwrap()), &(site_username.unwrap()), &(site_password.unwrap())).await {
-                Ok(_) => {
-                    println!("Credential saved successfully");
-                    match get_credentials(&binding, &user).await {
-                        Ok(creds) => {
-                            data.credentials = Arc::new(creds);
-                        }
+                }
+            }
+            (saved, saved_items, failed)
+        })
+    });
+
+    data.import_rows = Arc::new(Vec::new());
+    data.import_items = Arc::new(Vec::new());
+    data.import_message = format!("Imported {} credentials and {} secure notes", saved, saved_items);
+    if failed > 0 {
+        data.import_message.push_str(&format!(", {} failed", failed));
+    }
+    my_child_update(binding, user, None, data);
+    my_child_items(binding, user, data);
+}
+
+//Reads the whole vault of the user for a backup, the previous passwords included
+fn my_child_backup(binding: &Arc<SqlitePool>, user: &str, data: &AppState) -> Result<backup::Backup, String> {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => return Err("The vault is locked".to_string()),
+    };
+    let content = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            let mut credentials = Vec::new();
+            for credential in get_credentials(binding, &key, user).await? {
+                let history = get_password_history(binding, &key, user, credential.id).await?;
+                credentials.push((credential, history));
+            }
+            let items = get_items(binding, &key, user).await?;
+            Ok::<_, sqlx::Error>((credentials, items))
+        })
+    });
+    match content {
+        Ok((credentials, items)) => Ok(backup::Backup::new(user, &credentials, &items, unix_time())),
+        Err(e) => {
+            eprintln!("Failed to read the vault: {}", e);
+            Err("Failed to read the vault".to_string())
+        }
+    }
+}
+
+//Writes a decrypted backup into the vault, nothing is written when one row fails
+fn my_child_restore(binding: &Arc<SqlitePool>, user: &str, content: &backup::Backup, mode: backup::RestoreMode, data: &mut AppState) {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => return,
+    };
+    let restore = backup::plan(content, mode, &data.credentials, &data.items);
+    let replace = mode == backup::RestoreMode::Replace;
+    let result = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            restore_vault(binding, &key, user, &restore.credentials, &restore.items, replace).await
+        })
+    });
+
+    data.backup_message = match result {
+        Ok(()) => {
+            let mut message = format!("{}\nRestored {} credentials and {} items", content.summary(), restore.credentials.len(), restore.items.len());
+            if restore.skipped > 0 {
+                message.push_str(&format!(", {} already in the vault", restore.skipped));
+            }
+            if content.username != user {
+                message.push_str(&format!("\nThe backup was made by {}", content.username));
+            }
+            message
+        }
+        Err(e) => {
+            eprintln!("Failed to restore the backup: {}", e);
+            "Failed to restore the backup, the vault was not changed".to_string()
+        }
+    };
+    my_child_update(binding, user, None, data);
+    my_child_items(binding, user, data);
+}
+
+//Builds the health report from the stored credentials and shows its rows
+fn my_child_health(binding: &Arc<SqlitePool>, user: &str, data: &mut AppState) -> Option<health::HealthReport> {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => return None,
+    };
+    let credentials = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            get_credentials(binding, &key, user).await
+        })
+    });
+    match credentials {
+        Ok(credentials) => {
+            let report = health::report(user, &credentials, password_max_age_days(), unix_time());
+            data.health = Arc::new(report.issues());
+            data.health_message = report.summary();
+            Some(report)
+        }
+        Err(e) => {
+            eprintln!("Failed to get credentials: {}", e);
+            data.health_message = "Failed to read the credentials".to_string();
+            None
+        }
+    }
+}
+
+//Returns whether the history was read, the credentials view says why it was not
+fn my_child_history(binding: &Arc<SqlitePool>, user: &str, id: i32, data: &mut AppState) -> bool {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => return false,
+    };
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            match get_password_history(binding, &key, user, id).await {
+                Ok(history) => {
+                    data.history = Arc::new(history);
+                    true
+                }
+                Err(e) => {
+                    eprintln!("Failed to get password history: {}", e);
+                    data.status_message = "Failed to read the password history".to_string();
+                    false
+                }
+            }
+        })
+    })
+}
+
+//Called once the fingerprint released the vault key: shows the decrypted credentials
+fn my_child_open_vault(pool: &Arc<SqlitePool>, user: &str, key: Arc<VaultKey>, data: &mut AppState) {
+    data.status_message = "".to_string();
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            if let Err(e) = encrypt_legacy_credentials(pool, &key, user).await {
+                eprintln!("Failed to encrypt legacy credentials: {}", e);
+                data.status_message = "Failed to encrypt the credentials saved by an earlier version, it is tried again at the next unlock".to_string();
+            }
+        });
+    });
+
+    data.vault_key = Some(key);
+    data.view = ViewSelector::Credentials;
+    my_child_update(pool, user, None, data);
+    my_child_items(pool, user, data);
+    my_child_fingers(pool, user, data);
+}
+
+fn my_child_items(pool: &Arc<SqlitePool>, user: &str, data: &mut AppState) {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => return,
+    };
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            match get_items(pool, &key, user).await {
+                Ok(items) => data.items = Arc::new(items),
+                Err(e) => {
+                    eprintln!("Failed to get items: {}", e);
+                    data.status_message = "Failed to read the items".to_string();
+                }
+            }
+        });
+    });
+}
+
+//Adds or updates the item described by the form, then reloads the items
+fn my_child_save_item(pool: &Arc<SqlitePool>, user: &str, data: &mut AppState) {
+    let (key, kind) = match (&data.vault_key, data.category) {
+        (Some(key), Some(kind)) => (Arc::clone(key), kind),
+        _ => return,
+    };
+    if data.item_title.trim().is_empty() {
+        data.status_message = "Please give the item a title".to_string();
+        return;
+    }
+    let item = Item {
+        id: data.editing_item.unwrap_or(0),
+        title: data.item_title.clone(),
+        content: ItemContent::from_fields(kind, &data.item_fields),
+    };
+    let result = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            match data.editing_item {
+                Some(_) => update_item(pool, &key, user, &item).await,
+                None => save_item(pool, &key, user, &item).await.map(|_| ()),
+            }
+        })
+    });
+    match result {
+        Ok(()) => {
+            data.status_message = format!("{} saved", item.title);
+            data.clear_item_form();
+        }
+        //The form is kept so that nothing typed is lost
+        Err(e) => {
+            eprintln!("Failed to save item: {}", e);
+            data.status_message = "Failed to save the item, it was kept in the form".to_string();
+        }
+    }
+    my_child_items(pool, user, data);
+}
+
+fn my_child_delete_item(pool: &Arc<SqlitePool>, user: &str, id: i64, data: &mut AppState) {
+    let result = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            delete_item(pool, user, id).await
+        })
+    });
+    match result {
+        Ok(_) => {
+            data.status_message = "Item deleted".to_string();
+            if data.editing_item == Some(id) {
+                data.clear_item_form();
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to delete item: {}", e);
+            data.status_message = "Failed to delete the item".to_string();
+        }
+    }
+    my_child_items(pool, user, data);
+}
+
+//Saves the credential when one is given, then reloads the table. Returns false when the
+//credential was refused or could not be saved
+fn my_child_update(binding: &Arc<SqlitePool>, user: &str, credential: Option<&Credential>, data: &mut AppState) -> bool {
+    let key = match &data.vault_key {
+        Some(key) => Arc::clone(key),
+        None => {
+            data.status_message = "Vault is locked".to_string();
+            return false;
+        }
+    };
+    let saved = task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+
+            let mut saved = true;
+            if let Some(credential) = credential {
+                if credential.site == "" || credential.site_username == "" || credential.site_password == "" {
+                    data.status_message = "Please fill in the site, username and password".to_string();
+                    saved = false;
+                } else {
+                    match save_credentials(&binding, &key, &user, credential).await {
+                        Ok(_) => data.status_message = format!("{} added", credential.site),
                         Err(e) => {
-                            eprintln!("Failed to get credentials: {}", e);
+                            eprintln!("Failed to save credential: {}", e);
+                            data.status_message = "Failed to save the credential, it was kept in the form".to_string();
+                            saved = false;
                         }
                     }
                 }
+            }
+            match get_credentials(&binding, &key, &user).await {
+                Ok(creds) => {
+                    data.credentials = Arc::new(creds);
+                }
                 Err(e) => {
-                    eprintln!("Failed to save credential: {}", e);
+                    eprintln!("Failed to get credentials: {}", e);
+                    data.status_message = "Failed to read the credentials".to_string();
                 }
             }
-    
-        });
+            saved
+        })
     });
+    refresh_otp_codes(data);
+    check_breaches(data);
+    saved
 }
 
-fn my_child_login(_username: String, pool: Arc<SqlitePool>) -> bool{
+//Verifies the finger against the templates of the user and releases the vault key
+//Err holds the message shown in the login view
+fn my_child_login(_username: String, pool: Arc<SqlitePool>) -> Result<Arc<VaultKey>, String> {
     let result = task::block_in_place (||  {
 
         let task_result = tokio::runtime::Builder::new_current_thread()
@@ -584,51 +2978,260 @@ fn my_child_login(_username: String, pool: Arc<SqlitePool>) -> bool{
         .block_on(async{
             println!("Username: {}", _username);
             if let Err(e) = call_fingerprint_capture().await {
-                eprintln!("Failed to call fingerprint capture: {}", e);
-    
-                return false;
+                return Err(format!("Failed to call fingerprint capture: {}", e));
+            }
+
+            let user = match get_user(&pool, &_username).await {
+                Ok(Some(user)) => user,
+                Ok(None) => return Err(format!("Unknown user {}", _username)),
+                Err(e) => return Err(format!("Failed to find user: {}", e)),
+            };
+            let templates = get_templates(&pool, user.id).await.map_err(|e| format!("Failed to load templates: {}", e))?;
+            println!("{} fingerprint templates loaded", templates.len());
+
+            let image_path = String::from("data/fingerprint_Input.bmp");
+            let res_image_try = process_fingerprint(&image_path);
+
+            //Login succeeds on any enrolled finger that releases the key. The transform of a
+            //template is keyed by its fuzzy extractor output, so the probe reproduces it first
+            let mut failure = "The fingerprint does not match".to_string();
+            for template in templates {
+                let (derived, wrapped) = match reproduce_release(&pool, template.id, &res_image_try).await {
+                    Ok(release) => release,
+                    Err(message) => {
+                        failure = message;
+                        continue;
+                    }
+                };
+                let score = template_score(&res_image_try, &template.template, &derived);
+                println!("{}: best match len = {}", template.finger.label(), score);
+                if score > MATCH_THRESHOLD {
+                    match open_release(&derived, &wrapped) {
+                        Ok(key) => {
+                            reindex_template(&pool, template.id, &res_image_try).await;
+                            return Ok(Arc::new(key));
+                        }
+                        Err(message) => failure = message,
+                    }
+                }
+            }
+            Err(failure)
+        });
+        task_result
+    });
+    result
+}
+
+//Protected templates cannot be indexed again from the database, a template whose index was
+//cleared gets the keys of the probe that just released its key
+async fn reindex_template(pool: &SqlitePool, template_id: i64, probe: &Vec<Vec<u8>>) {
+    match has_template_keys(pool, template_id).await {
+        Ok(false) => {
+            let saved = match index_keys(&[probe.clone()]) {
+                Ok(keys) => save_template_keys(pool, template_id, &keys).await.map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = saved {
+                eprintln!("Failed to index template {}: {}", template_id, e);
             }
+        }
+        Ok(true) => {}
+        Err(e) => eprintln!("Failed to read the index of template {}: {}", template_id, e),
+    }
+}
 
-            let user = get_user(&pool, &_username).await.expect("Failed to find user");
-            println!("User: {:?} found", user);
+//Fuzzy extractor output the probe reproduces from the key release data of a template, with the
+//wrapped vault key. A vault key is never generated or read in clear here: a finger that cannot
+//release the key sends the user to the master password or the recovery key. Err holds the
+//message shown.
+async fn reproduce_release(pool: &SqlitePool, template_id: i64, probe: &Vec<Vec<u8>>) -> Result<([u8; 32], Vec<u8>), String> {
+    let (helper, wrapped) = get_key_release(pool, template_id)
+        .await
+        .map_err(|e| format!("Failed to load key release data: {}", e))?
+        .ok_or_else(|| NO_KEY_RELEASE.to_string())?;
+    let derived = fuzzy::reproduce(&detect_minutiae(probe), &helper, FINGERPRINT_ROWS, FINGERPRINT_COLS)
+        .ok_or_else(|| NO_KEY_RELEASE.to_string())?;
+    Ok((derived, wrapped))
+}
 
-            if user.is_some() {
+//Unwraps the vault key of a matched template
+fn open_release(derived: &[u8; 32], wrapped: &[u8]) -> Result<VaultKey, String> {
+    let wrapping_key = VaultKey::from_bytes(derived).unwrap();
+    vault::decrypt(&wrapping_key, "vault_key", wrapped)
+        .and_then(|key| VaultKey::from_bytes(&key))
+        .ok_or_else(|| "The finger matched but did not release the vault key, please try again".to_string())
+}
 
-                let vec1 = user.unwrap().fingerprint_image;
-                let vec2 = reconstruct(vec1, 80, 64);
+//1:N identification, returns the user owning the best template above the threshold
+//Err holds the message shown in the login view
+fn my_child_identify(pool: Arc<SqlitePool>) -> Result<(String, Arc<VaultKey>), String> {
+    let result = task::block_in_place (||  {
 
-                println!("Fingerprint image loaded");
-                
+        let task_result = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            if let Err(e) = call_fingerprint_capture().await {
+                return Err(format!("Failed to call fingerprint capture: {}", e));
+            }
 
-                let image_path = String::from("data/fingerprint_Input.bmp");
-                let hist_try = histogram_equalization(&image_path);
-                let bin_try = binarization(hist_try.clone(), 128);
-                let thin_try = thin(&bin_try);
-                let minutia_try = mark_minutia(&thin_try);
-                let res_image_try = remove_false_minutia(thin_try, minutia_try, 10.0, 0.5);
+            let image_path = String::from("data/fingerprint_Input.bmp");
+            let res_image_try = process_fingerprint(&image_path);
+
+            //Only the templates sharing the most triplets with the probe are fully matched
+            let probe_points = index_minutiae(&res_image_try);
+            let probe_keys = index::seal(&index_secret()?, &index::triplet_keys(&probe_points, FINGERPRINT_ROWS, FINGERPRINT_COLS));
+            let votes = get_template_votes(&pool, &probe_keys).await.map_err(|e| format!("Failed to query the index: {}", e))?;
+            let candidates = index::rank_candidates(votes, index_candidates());
+            println!("{} candidates from the index", candidates.len());
+
+            let mut best: Option<(String, usize, [u8; 32], Vec<u8>)> = None;
+            for id in candidates {
+                let (username, template) = match get_template_with_user(&pool, id).await {
+                    Ok(Some(found)) => found,
+                    _ => continue,
+                };
+                let (derived, wrapped) = match reproduce_release(&pool, template.id, &res_image_try).await {
+                    Ok(release) => release,
+                    Err(_) => continue,
+                };
+                let score = template_score(&res_image_try, &template.template, &derived);
+                if score > MATCH_THRESHOLD && best.as_ref().map_or(true, |(_, best_score, _, _)| score > *best_score) {
+                    best = Some((username, score, derived, wrapped));
+                }
+            }
 
-                println!("About to do mathces");
-                let matches = minutiae_matching(&res_image_try, &vec2);
-                println!("Matches: {:?} \nMatches len = {}", matches, matches.len());
-                if matches.len() > 120 {
-                    println!("Fingerprints match!");
-                    return true;
-                } else {
-                    println!("Fingerprints do not match!");
-                    return false;
+            match best {
+                Some((username, score, derived, wrapped)) => {
+                    println!("Identified {} ({} matches)", username, score);
+                    let key = open_release(&derived, &wrapped)?;
+                    Ok((username, Arc::new(key)))
                 }
-                return matches.len() > 120;
-            } else {
-                println!("User not found");
+                //The index is cleared when its secret is created, until the next login with the username
+                None => Err("No enrolled finger matches. If your finger was enrolled before this version, log in with your username once.".to_string()),
             }
-            false
         });
         task_result
     });
     result
 }
 
-fn my_child_register(_username: String, pool: Arc<SqlitePool>) {
+fn my_child_unlock_password(pool: &Arc<SqlitePool>, user: &str, password: &str) -> Option<Arc<VaultKey>> {
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            let user = get_user(pool, user).await.ok()??;
+            let fallback = get_vault_recovery(pool, user.id).await.ok()?;
+            let wrapping_key = recovery::password_key(password, &fallback.password_salt?);
+            recovery::unwrap(&wrapping_key, &fallback.password_wrapped_key?).map(Arc::new)
+        })
+    })
+}
+
+fn my_child_unlock_recovery(pool: &Arc<SqlitePool>, user: &str, recovery_key: &str) -> Option<Arc<VaultKey>> {
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            let user = get_user(pool, user).await.ok()??;
+            let fallback = get_vault_recovery(pool, user.id).await.ok()?;
+            let wrapping_key = recovery::recovery_key(recovery_key)?;
+            recovery::unwrap(&wrapping_key, &fallback.recovery_wrapped_key?).map(Arc::new)
+        })
+    })
+}
+
+//Sets or changes the master password, the vault has to be unlocked
+fn my_child_set_master_password(pool: &Arc<SqlitePool>, user: &str, password: &str, key: &VaultKey) -> bool {
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            let user = match get_user(pool, user).await {
+                Ok(Some(user)) => user,
+                _ => return false,
+            };
+            let salt = recovery::new_salt();
+            let wrapped = recovery::wrap(&recovery::password_key(password, &salt), key);
+            match save_master_password(pool, user.id, salt, wrapped).await {
+                Ok(_) => true,
+                Err(e) => {
+                    eprintln!("Failed to save the master password: {}", e);
+                    false
+                }
+            }
+        })
+    })
+}
+
+//Issues a new recovery key, returned once to be displayed and never stored
+fn my_child_new_recovery_key(pool: &Arc<SqlitePool>, user: &str, key: &VaultKey) -> Option<String> {
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            let user = get_user(pool, user).await.ok()??;
+            let recovery_key = recovery::new_recovery_key();
+            let wrapped = recovery::wrap(&recovery::recovery_key(&recovery_key)?, key);
+            match save_recovery_key(pool, user.id, wrapped).await {
+                Ok(_) => Some(recovery_key),
+                Err(e) => {
+                    eprintln!("Failed to save the recovery key: {}", e);
+                    None
+                }
+            }
+        })
+    })
+}
+
+//Captures several impressions of the same finger and checks that they all match
+//each other, they are stored together so login can match best-of-N
+//Err holds the message shown to the user
+async fn capture_template() -> Result<Vec<Vec<Vec<u8>>>, String> {
+    let count = enroll_impressions();
+    let mut impressions: Vec<Vec<Vec<u8>>> = Vec::new();
+
+    for i in 0..count {
+        println!("Impression {}/{}", i + 1, count);
+        if let Err(e) = call_fingerprint_capture().await {
+            eprintln!("Failed to call fingerprint capture: {}", e);
+
+            return Err("The fingerprint sensor could not be read, enrolment cancelled".to_string());
+        }
+
+        let image_path = String::from("data/fingerprint_Input.bmp");
+        let res_image_try = process_fingerprint(&image_path);
+
+        //Every new impression has to match all the previous ones
+        for (j, previous) in impressions.iter().enumerate() {
+            let score = minutiae_matching(&res_image_try, previous).len();
+            if score <= MATCH_THRESHOLD {
+                println!("Impression {} does not match impression {} ({} matches)", i + 1, j + 1, score);
+                return Err(format!("Impression {} does not match impression {}, enrolment cancelled. Place the same finger the same way each time", i + 1, j + 1));
+            }
+        }
+        impressions.push(res_image_try);
+    }
+
+    Ok(impressions)
+}
+
+//Err holds the message shown in the register view
+fn my_child_register(_username: String, finger: Finger, pool: Arc<SqlitePool>) -> Result<Arc<VaultKey>, String> {
     let result = task::block_in_place (||  {
 
         let task_result = tokio::runtime::Builder::new_current_thread()
@@ -637,26 +3240,128 @@ fn my_child_register(_username: String, pool: Arc<SqlitePool>) {
         .unwrap()
         .block_on(async{
             println!("Username: {}", _username);
-            if let Err(e) = call_fingerprint_capture().await {
-                eprintln!("Failed to call fingerprint capture: {}", e);
-    
-                return;
+            match get_user(&pool, &_username).await {
+                Ok(None) => {}
+                Ok(Some(_)) => return Err(format!("The username {} is already taken", _username)),
+                Err(e) => {
+                    eprintln!("Failed to look up user: {}", e);
+                    return Err("Failed to read the users, please try again".to_string());
+                }
+            }
+            let impressions = capture_template().await?;
+
+            //The vault key only exists wrapped by the keys released by the fingers
+            let key = VaultKey::generate();
+            let keys = index_keys(&impressions)?;
+            let (protected, helper, wrapped) = bind_template(&impressions, &key);
+            //The user is only created together with its finger
+            if let Err(e) = save_user(&pool, &_username, finger, protected, &keys, helper, wrapped).await {
+                eprintln!("Failed to save user {}: {}", _username, e);
+                return Err(format!("Failed to create the user {}, nothing was saved: register again", _username));
             }
-            
-            let image_path = String::from("data/fingerprint_Input.bmp");
-            let hist_try = histogram_equalization(&image_path);
-            let bin_try = binarization(hist_try.clone(), 128);
-            let thin_try = thin(&bin_try);
-            let minutia_try = mark_minutia(&thin_try);
-            let res_image_try = remove_false_minutia(thin_try, minutia_try, 10.0, 0.5);
-            
-            save_user(&pool, &_username, flatten(res_image_try)).await.expect("Failed to save user");
             println!("User saved successfully");
-    
+            Ok(Arc::new(key))
+        });
+        task_result
+    });
+    result
+}
+
+//Enrols another finger for a user that is already logged in, returns the message shown in
+//the Fingers view
+fn my_child_enroll_finger(pool: &Arc<SqlitePool>, user: &str, finger: Finger, key: &VaultKey) -> String {
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            let user = match get_user(pool, user).await {
+                Ok(Some(user)) => user,
+                _ => {
+                    eprintln!("Failed to find user {}", user);
+                    return format!("Unknown user {}", user);
+                }
+            };
+            let impressions = match capture_template().await {
+                Ok(impressions) => impressions,
+                Err(message) => return message,
+            };
+            let keys = match index_keys(&impressions) {
+                Ok(keys) => keys,
+                Err(message) => return message,
+            };
+            let (protected, helper, wrapped) = bind_template(&impressions, key);
+            //The previous template of the finger is only replaced when the new one is saved
+            match save_template(pool, user.id, finger, protected, &keys, helper, wrapped).await {
+                Ok(_) => format!("{} enrolled", finger.label()),
+                Err(e) => {
+                    eprintln!("Failed to save template: {}", e);
+                    format!("Failed to save the {}, nothing was changed: enrol it again", finger.label().to_lowercase())
+                }
+            }
+        })
+    })
+}
+
+//Returns the message shown in the Fingers view
+fn my_child_remove_finger(pool: &Arc<SqlitePool>, user: &str, finger: Finger) -> String {
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            remove_finger(pool, user, finger).await.unwrap_or_else(|e| format!("Failed to remove the {}: {}", finger.label().to_lowercase(), e))
+        })
+    })
+}
+
+//A finger is only removed when another enrolled finger can still release the vault key,
+//otherwise the user would be locked out of the fingerprint login
+async fn remove_finger(pool: &SqlitePool, user: &str, finger: Finger) -> Result<String, sqlx::Error> {
+    let user = match get_user(pool, user).await? {
+        Some(user) => user,
+        None => return Ok(format!("Unknown user {}", user)),
+    };
+    let templates = get_templates(pool, user.id).await?;
+    if !templates.iter().any(|template| template.finger == finger) {
+        return Ok(format!("The {} is not enrolled", finger.label().to_lowercase()));
+    }
+    let mut releases = 0;
+    for template in templates.iter().filter(|template| template.finger != finger) {
+        if get_key_release(pool, template.id).await?.is_some() {
+            releases += 1;
+        }
+    }
+    if releases == 0 {
+        return Ok("No other enrolled finger unlocks the vault, enrol another finger before removing this one".to_string());
+    }
+    delete_templates(pool, user.id, finger).await?;
+    Ok(format!("The {} was removed", finger.label().to_lowercase()))
+}
+
+//Refreshes the list of enrolled fingers displayed in the Fingers view
+fn my_child_fingers(pool: &Arc<SqlitePool>, user: &str, data: &mut AppState) {
+    task::block_in_place (||  {
+
+        tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async{
+            let templates = match get_user(pool, user).await {
+                Ok(Some(user)) => get_templates(pool, user.id).await.unwrap_or_default(),
+                _ => Vec::new(),
+            };
+            data.fingers = Arc::new(templates.into_iter().map(|template| EnrolledFinger {
+                id: template.id,
+                finger: template.finger,
+            }).collect());
         });
     });
-    
-    
 }
 
 struct AppDelegate {
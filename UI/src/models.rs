@@ -1,16 +1,461 @@
 use druid::{Data, Lens};
+use serde::{Deserialize, Serialize};
 #[derive(Debug)]
 pub struct User {
     pub id: i64,
     pub username: String,
-    pub fingerprint_image: Vec<u8>,
 }
 
+//Finger position a template was enrolled with, stored as text in the templates table
+#[derive(Debug, Clone, Copy, Data, PartialEq)]
+pub enum Finger {
+    RightThumb,
+    RightIndex,
+    RightMiddle,
+    RightRing,
+    RightLittle,
+    LeftThumb,
+    LeftIndex,
+    LeftMiddle,
+    LeftRing,
+    LeftLittle,
+    //Templates enrolled before fingers were labelled
+    Unknown,
+}
+
+impl Finger {
+    pub const ALL: [Finger; 10] = [
+        Finger::RightThumb,
+        Finger::RightIndex,
+        Finger::RightMiddle,
+        Finger::RightRing,
+        Finger::RightLittle,
+        Finger::LeftThumb,
+        Finger::LeftIndex,
+        Finger::LeftMiddle,
+        Finger::LeftRing,
+        Finger::LeftLittle,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Finger::RightThumb => "right_thumb",
+            Finger::RightIndex => "right_index",
+            Finger::RightMiddle => "right_middle",
+            Finger::RightRing => "right_ring",
+            Finger::RightLittle => "right_little",
+            Finger::LeftThumb => "left_thumb",
+            Finger::LeftIndex => "left_index",
+            Finger::LeftMiddle => "left_middle",
+            Finger::LeftRing => "left_ring",
+            Finger::LeftLittle => "left_little",
+            Finger::Unknown => "unknown",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Finger {
+        Finger::ALL
+            .iter()
+            .copied()
+            .find(|finger| finger.as_str() == value)
+            .unwrap_or(Finger::Unknown)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Finger::RightThumb => "Right thumb",
+            Finger::RightIndex => "Right index",
+            Finger::RightMiddle => "Right middle",
+            Finger::RightRing => "Right ring",
+            Finger::RightLittle => "Right little",
+            Finger::LeftThumb => "Left thumb",
+            Finger::LeftIndex => "Left index",
+            Finger::LeftMiddle => "Left middle",
+            Finger::LeftRing => "Left ring",
+            Finger::LeftLittle => "Left little",
+            Finger::Unknown => "Unknown finger",
+        }
+    }
+
+    //Next finger in the selector, used by the "Finger" button
+    pub fn next(&self) -> Finger {
+        let index = Finger::ALL.iter().position(|finger| finger == self).unwrap_or(Finger::ALL.len() - 1);
+        Finger::ALL[(index + 1) % Finger::ALL.len()]
+    }
+}
+
+#[derive(Debug)]
+pub struct Template {
+    pub id: i64,
+    pub user_id: i64,
+    pub finger: Finger,
+    pub template: Vec<u8>,
+}
+
+//Fallback ways to unwrap the vault key, both are optional
+#[derive(Debug, Default)]
+pub struct VaultRecovery {
+    pub password_salt: Option<Vec<u8>>,
+    pub password_wrapped_key: Option<Vec<u8>>,
+    pub recovery_wrapped_key: Option<Vec<u8>>,
+}
+
+//Light version of Template displayed in the fingers list
 #[derive(Debug, Clone, Data, Lens, PartialEq)]
+pub struct EnrolledFinger {
+    pub id: i64,
+    pub finger: Finger,
+}
+
+#[derive(Debug, Clone, Data, Lens, PartialEq, Default)]
 pub struct Credential {
     pub id: i32,
     pub username: String,
     pub site: String,
     pub site_username: String,
     pub site_password: String,
-}
\ No newline at end of file
+    pub url: String,
+    pub notes: String,
+    //Comma separated, also used as folders
+    pub tags: String,
+    pub favourite: bool,
+    //otpauth:// URI of the 2FA secret, empty when none
+    pub otp: String,
+    //Current one-time code with its countdown, refreshed every second by the UI
+    pub otp_code: String,
+    //Times the password was seen in the offline breach dataset, 0 when not found or not checked
+    pub breaches: u64,
+    //Strength score of the password from 0 to 4 (see strength.rs), estimated once when loaded
+    pub strength: u8,
+    //UTC dates for display, empty when unknown
+    pub created_at: String,
+    pub modified_at: String,
+    pub last_used_at: String,
+    pub password_changed_at: String,
+}
+
+//Login read from an import file, shown in the preview before anything is saved.
+//Duplicates of stored credentials or of earlier lines are not imported.
+#[derive(Debug, Clone, Data, Lens, PartialEq)]
+pub struct ImportRow {
+    pub line: usize,
+    pub credential: Credential,
+    pub duplicate: bool,
+}
+
+//Row of the vault health report, issue is Reused, Weak or Old
+#[derive(Debug, Clone, Data, Lens, PartialEq)]
+pub struct HealthIssue {
+    pub id: i32,
+    pub site: String,
+    pub site_username: String,
+    pub issue: String,
+    pub detail: String,
+}
+
+//Previous password of a credential, changed_at is a UTC date for display
+#[derive(Debug, Clone, Data, Lens, PartialEq)]
+pub struct PasswordHistory {
+    pub id: i64,
+    pub credential_id: i32,
+    pub site_password: String,
+    pub changed_at: String,
+}
+
+//Type of a vault item other than a site login, stored as text in the items table
+#[derive(Debug, Clone, Copy, Data, PartialEq)]
+pub enum ItemKind {
+    SecureNote,
+    Card,
+    Identity,
+    SshKey,
+    ApiToken,
+}
+
+impl ItemKind {
+    pub const ALL: [ItemKind; 5] = [
+        ItemKind::SecureNote,
+        ItemKind::Card,
+        ItemKind::Identity,
+        ItemKind::SshKey,
+        ItemKind::ApiToken,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemKind::SecureNote => "secure_note",
+            ItemKind::Card => "card",
+            ItemKind::Identity => "identity",
+            ItemKind::SshKey => "ssh_key",
+            ItemKind::ApiToken => "api_token",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<ItemKind> {
+        ItemKind::ALL.iter().copied().find(|kind| kind.as_str() == value)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ItemKind::SecureNote => "Secure notes",
+            ItemKind::Card => "Payment cards",
+            ItemKind::Identity => "Identities",
+            ItemKind::SshKey => "SSH keys",
+            ItemKind::ApiToken => "API tokens",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Data, PartialEq, Default)]
+pub struct SecureNote {
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Data, PartialEq, Default)]
+pub struct Card {
+    pub cardholder: String,
+    pub number: String,
+    //MM/YY
+    pub expiry: String,
+    pub security_code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Data, PartialEq, Default)]
+pub struct Identity {
+    pub full_name: String,
+    pub email: String,
+    pub phone: String,
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Data, PartialEq, Default)]
+pub struct SshKey {
+    pub public_key: String,
+    pub private_key: String,
+    pub passphrase: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Data, PartialEq, Default)]
+pub struct ApiToken {
+    pub service: String,
+    pub token: String,
+    pub expires: String,
+}
+
+//Fields of an item, serialized to JSON and encrypted as a whole in items.payload
+#[derive(Serialize, Deserialize, Debug, Clone, Data, PartialEq)]
+#[serde(tag = "kind", content = "fields", rename_all = "snake_case")]
+pub enum ItemContent {
+    SecureNote(SecureNote),
+    Card(Card),
+    Identity(Identity),
+    SshKey(SshKey),
+    ApiToken(ApiToken),
+}
+
+//One labelled value of the item form
+#[derive(Debug, Clone, Data, Lens, PartialEq)]
+pub struct ItemField {
+    pub label: String,
+    pub value: String,
+}
+
+fn field(label: &str, value: &str) -> ItemField {
+    ItemField { label: label.to_string(), value: value.to_string() }
+}
+
+impl ItemContent {
+    pub fn kind(&self) -> ItemKind {
+        match self {
+            ItemContent::SecureNote(_) => ItemKind::SecureNote,
+            ItemContent::Card(_) => ItemKind::Card,
+            ItemContent::Identity(_) => ItemKind::Identity,
+            ItemContent::SshKey(_) => ItemKind::SshKey,
+            ItemContent::ApiToken(_) => ItemKind::ApiToken,
+        }
+    }
+
+    pub fn empty(kind: ItemKind) -> ItemContent {
+        match kind {
+            ItemKind::SecureNote => ItemContent::SecureNote(SecureNote::default()),
+            ItemKind::Card => ItemContent::Card(Card::default()),
+            ItemKind::Identity => ItemContent::Identity(Identity::default()),
+            ItemKind::SshKey => ItemContent::SshKey(SshKey::default()),
+            ItemKind::ApiToken => ItemContent::ApiToken(ApiToken::default()),
+        }
+    }
+
+    //Fields in form order, from_fields reads them back in the same order
+    pub fn fields(&self) -> Vec<ItemField> {
+        match self {
+            ItemContent::SecureNote(note) => vec![field("Text", &note.text)],
+            ItemContent::Card(card) => vec![
+                field("Cardholder", &card.cardholder),
+                field("Number", &card.number),
+                field("Expiry (MM/YY)", &card.expiry),
+                field("Security code", &card.security_code),
+            ],
+            ItemContent::Identity(identity) => vec![
+                field("Full name", &identity.full_name),
+                field("Email", &identity.email),
+                field("Phone", &identity.phone),
+                field("Address", &identity.address),
+            ],
+            ItemContent::SshKey(key) => vec![
+                field("Public key", &key.public_key),
+                field("Private key", &key.private_key),
+                field("Passphrase", &key.passphrase),
+            ],
+            ItemContent::ApiToken(token) => vec![
+                field("Service", &token.service),
+                field("Token", &token.token),
+                field("Expires", &token.expires),
+            ],
+        }
+    }
+
+    pub fn from_fields(kind: ItemKind, fields: &[ItemField]) -> ItemContent {
+        let value = |i: usize| fields.get(i).map(|field| field.value.clone()).unwrap_or_default();
+        match kind {
+            ItemKind::SecureNote => ItemContent::SecureNote(SecureNote { text: value(0) }),
+            ItemKind::Card => ItemContent::Card(Card {
+                cardholder: value(0),
+                number: value(1),
+                expiry: value(2),
+                security_code: value(3),
+            }),
+            ItemKind::Identity => ItemContent::Identity(Identity {
+                full_name: value(0),
+                email: value(1),
+                phone: value(2),
+                address: value(3),
+            }),
+            ItemKind::SshKey => ItemContent::SshKey(SshKey {
+                public_key: value(0),
+                private_key: value(1),
+                passphrase: value(2),
+            }),
+            ItemKind::ApiToken => ItemContent::ApiToken(ApiToken {
+                service: value(0),
+                token: value(1),
+                expires: value(2),
+            }),
+        }
+    }
+
+    //Shown in the items list, never contains the secret fields
+    pub fn summary(&self) -> String {
+        match self {
+            ItemContent::SecureNote(note) => format!("{} characters", note.text.chars().count()),
+            ItemContent::Card(card) => {
+                let digits: Vec<char> = card.number.chars().filter(|c| c.is_ascii_digit()).collect();
+                let last: String = digits[digits.len().saturating_sub(4)..].iter().collect();
+                format!("•••• {}  {}", last, card.expiry)
+            }
+            ItemContent::Identity(identity) => format!("{} {}", identity.full_name, identity.email),
+            ItemContent::SshKey(key) => {
+                //Key type and comment of an OpenSSH public key
+                let parts: Vec<&str> = key.public_key.split_whitespace().collect();
+                match parts.as_slice() {
+                    [kind, _, comment @ ..] => format!("{} {}", kind, comment.join(" ")),
+                    _ => String::new(),
+                }
+            }
+            ItemContent::ApiToken(token) => format!("{} {}", token.service, token.expires),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Data, Lens, PartialEq)]
+pub struct Item {
+    pub id: i64,
+    pub title: String,
+    pub content: ItemContent,
+}
+
+//Kind of password produced by the generator of the credentials form
+#[derive(Debug, Clone, Copy, Data, PartialEq)]
+pub enum GeneratorMode {
+    Random,
+    Pronounceable,
+    Diceware,
+}
+
+impl GeneratorMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            GeneratorMode::Random => "Random",
+            GeneratorMode::Pronounceable => "Pronounceable",
+            GeneratorMode::Diceware => "Diceware passphrase",
+        }
+    }
+
+    pub fn next(&self) -> GeneratorMode {
+        match self {
+            GeneratorMode::Random => GeneratorMode::Pronounceable,
+            GeneratorMode::Pronounceable => GeneratorMode::Diceware,
+            GeneratorMode::Diceware => GeneratorMode::Random,
+        }
+    }
+}
+
+//File layout of the plaintext export, named after the managers that import it
+#[derive(Debug, Clone, Copy, Data, PartialEq)]
+pub enum ExportLayout {
+    BitwardenJson,
+    BitwardenCsv,
+    ChromeCsv,
+}
+
+impl ExportLayout {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExportLayout::BitwardenJson => "Bitwarden JSON",
+            ExportLayout::BitwardenCsv => "Bitwarden CSV",
+            ExportLayout::ChromeCsv => "Chrome / Edge / Firefox CSV",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportLayout::BitwardenJson => "json",
+            ExportLayout::BitwardenCsv | ExportLayout::ChromeCsv => "csv",
+        }
+    }
+
+    pub fn next(&self) -> ExportLayout {
+        match self {
+            ExportLayout::BitwardenJson => ExportLayout::BitwardenCsv,
+            ExportLayout::BitwardenCsv => ExportLayout::ChromeCsv,
+            ExportLayout::ChromeCsv => ExportLayout::BitwardenJson,
+        }
+    }
+}
+
+//Options of the generator, the sliders give f64 values
+#[derive(Debug, Clone, Data, Lens, PartialEq)]
+pub struct GeneratorSettings {
+    pub mode: GeneratorMode,
+    pub length: f64,
+    pub words: f64,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    pub exclude_ambiguous: bool,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        GeneratorSettings {
+            mode: GeneratorMode::Random,
+            length: 20.0,
+            words: 5.0,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: true,
+        }
+    }
+}
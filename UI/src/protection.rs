@@ -0,0 +1,199 @@
+//Cancelable template protection using a cartesian block transform (Ratha et al.).
+//The image is cut in cells and every cell is moved to a cell picked by a per-template key.
+//The cells land on half as many targets so the transform is many-to-one: even with the key
+//the stored points cannot be mapped back to the original print. Points inside a cell keep
+//their relative positions, so a probe transformed with the same key still matches.
+//The key is never stored: it is the output of the fuzzy extractor, which only a probe of the
+//enrolled finger reproduces from the key release data of the template. A leaked template is
+//revoked by enrolling the finger again, which draws a new fuzzy extractor secret.
+use sha2::{Digest, Sha256};
+
+//Size in pixels of the transform cells
+const CELL: usize = 8;
+//Marks the blobs holding a protected template, older blobs are raw skeleton images
+const MAGIC: &[u8; 4] = b"BGC2";
+//Same tolerance as minutiae_matching
+const MATCH_DISTANCE: f64 = 5.0;
+
+//Target cell of every cell, drawn with replacement among the first half of the cells so at
+//least half of the cells share their target with another one
+fn cell_mapping(key: &[u8; 32], rows: usize, cols: usize) -> Vec<usize> {
+    let targets = (rows * cols / 2).max(1) as u64;
+    (0..rows * cols)
+        .map(|cell| {
+            let mut hasher = Sha256::new();
+            hasher.update(b"bioguard-template-transform");
+            hasher.update(key);
+            hasher.update((cell as u32).to_le_bytes());
+            let digest = hasher.finalize();
+            (u64::from_le_bytes(digest[..8].try_into().unwrap()) % targets) as usize
+        })
+        .collect()
+}
+
+//Applies the keyed transform to the points of an impression
+pub fn transform(points: &[(usize, usize)], key: &[u8; 32], height: usize, width: usize) -> Vec<(usize, usize)> {
+    let rows = height.div_ceil(CELL);
+    let cols = width.div_ceil(CELL);
+    let mapping = cell_mapping(key, rows, cols);
+
+    points
+        .iter()
+        .map(|&(x, y)| {
+            let target = mapping[(x / CELL) * cols + y / CELL];
+            ((target / cols) * CELL + x % CELL, (target % cols) * CELL + y % CELL)
+        })
+        .collect()
+}
+
+//Counts the probe points having a stored point close enough, like minutiae_matching
+pub fn count_matches(probe: &[(usize, usize)], stored: &[(usize, usize)]) -> usize {
+    probe
+        .iter()
+        .filter(|&&(px, py)| {
+            stored.iter().any(|&(sx, sy)| {
+                let distance = ((px as f64 - sx as f64).powi(2) + (py as f64 - sy as f64).powi(2)).sqrt();
+                distance < MATCH_DISTANCE
+            })
+        })
+        .count()
+}
+
+pub fn is_protected(blob: &[u8]) -> bool {
+    blob.starts_with(MAGIC)
+}
+
+//Blob layout: MAGIC, impression count (u16 LE),
+//then for each impression a point count (u16 LE) followed by the points (x, y as u16 LE)
+pub fn encode(impressions: &[Vec<(usize, usize)>]) -> Vec<u8> {
+    let mut blob = Vec::new();
+    blob.extend_from_slice(MAGIC);
+    blob.extend_from_slice(&(impressions.len() as u16).to_le_bytes());
+    for points in impressions {
+        blob.extend_from_slice(&(points.len() as u16).to_le_bytes());
+        for &(x, y) in points {
+            blob.extend_from_slice(&(x as u16).to_le_bytes());
+            blob.extend_from_slice(&(y as u16).to_le_bytes());
+        }
+    }
+    blob
+}
+
+fn read_u16(blob: &[u8], offset: &mut usize) -> Option<u16> {
+    let bytes = blob.get(*offset..*offset + 2)?;
+    *offset += 2;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn decode(blob: &[u8]) -> Option<Vec<Vec<(usize, usize)>>> {
+    if !blob.starts_with(MAGIC) {
+        return None;
+    }
+    let mut offset = MAGIC.len();
+    let count = read_u16(blob, &mut offset)?;
+    let mut impressions = Vec::new();
+    for _ in 0..count {
+        let len = read_u16(blob, &mut offset)?;
+        let mut points = Vec::new();
+        for _ in 0..len {
+            let x = read_u16(blob, &mut offset)? as usize;
+            let y = read_u16(blob, &mut offset)? as usize;
+            points.push((x, y));
+        }
+        impressions.push(points);
+    }
+    Some(impressions)
+}
+
+//Protects the impressions of a finger with the key released by its fuzzy extractor
+pub fn protect(impressions: &[Vec<(usize, usize)>], key: &[u8; 32], height: usize, width: usize) -> Vec<u8> {
+    let transformed: Vec<Vec<(usize, usize)>> = impressions
+        .iter()
+        .map(|points| transform(points, key, height, width))
+        .collect();
+    encode(&transformed)
+}
+
+//Best-of-N score of the probe points against a protected template, the key being the one the
+//probe reproduced. A wrong key moves the cells elsewhere and the probe does not match.
+pub fn score(probe: &[(usize, usize)], blob: &[u8], key: &[u8; 32], height: usize, width: usize) -> usize {
+    match decode(blob) {
+        Some(impressions) => {
+            let transformed = transform(probe, key, height, width);
+            impressions
+                .iter()
+                .map(|stored| count_matches(&transformed, stored))
+                .max()
+                .unwrap_or(0)
+        }
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const HEIGHT: usize = 80;
+    const WIDTH: usize = 64;
+
+    fn points(seed: u64, count: usize) -> Vec<(usize, usize)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count).map(|_| (rng.gen_range(0..HEIGHT), rng.gen_range(0..WIDTH))).collect()
+    }
+
+    //Another capture of the same points, each one moved by a pixel at most
+    fn jitter(points: &[(usize, usize)], seed: u64) -> Vec<(usize, usize)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        points
+            .iter()
+            .map(|&(x, y)| {
+                let x = (x as i64 + rng.gen_range(-1..=1)).clamp(0, HEIGHT as i64 - 1);
+                let y = (y as i64 + rng.gen_range(-1..=1)).clamp(0, WIDTH as i64 - 1);
+                (x as usize, y as usize)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn probe_matches_with_the_same_key() {
+        let enrolled = points(1, 12);
+        let key = [1u8; 32];
+        let blob = protect(std::slice::from_ref(&enrolled), &key, HEIGHT, WIDTH);
+        assert!(score(&jitter(&enrolled, 2), &blob, &key, HEIGHT, WIDTH) >= 9);
+    }
+
+    #[test]
+    fn leaked_template_does_not_match_after_reenrolment() {
+        let enrolled = points(1, 12);
+        let (old_key, new_key) = ([1u8; 32], [2u8; 32]);
+        let leaked = protect(std::slice::from_ref(&enrolled), &old_key, HEIGHT, WIDTH);
+        let reissued = protect(std::slice::from_ref(&enrolled), &new_key, HEIGHT, WIDTH);
+
+        //Neither the leaked points nor the leaked key match the template enrolled again
+        let leaked_points = decode(&leaked).unwrap().remove(0);
+        let reissued_points = decode(&reissued).unwrap().remove(0);
+        assert!(count_matches(&leaked_points, &reissued_points) < 6);
+        assert!(score(&jitter(&enrolled, 2), &reissued, &old_key, HEIGHT, WIDTH) < 6);
+        assert!(score(&jitter(&enrolled, 2), &reissued, &new_key, HEIGHT, WIDTH) >= 9);
+    }
+
+    #[test]
+    fn transform_is_many_to_one() {
+        let rows = HEIGHT.div_ceil(CELL);
+        let cols = WIDTH.div_ceil(CELL);
+        let mut targets = cell_mapping(&[3u8; 32], rows, cols);
+        targets.sort();
+        targets.dedup();
+        assert!(targets.len() <= rows * cols / 2);
+    }
+
+    #[test]
+    fn blob_does_not_hold_the_key() {
+        let enrolled = points(1, 12);
+        let blob = protect(&[enrolled], &[4u8; 32], HEIGHT, WIDTH);
+        assert_eq!(blob.len(), MAGIC.len() + 2 + 2 + 12 * 4);
+    }
+}
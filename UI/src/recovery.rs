@@ -0,0 +1,128 @@
+//Fallbacks unwrapping the vault key when the fingerprint cannot be used: an optional master
+//password stretched with Argon2id, and a printable recovery key holding 160 random bits.
+//Both only ever store the vault key wrapped with the key they derive.
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::vault::{self, VaultKey};
+
+const SALT_LEN: usize = 16;
+const RECOVERY_KEY_LEN: usize = 20;
+//Argon2id cost: 64 MiB, 3 passes, 1 lane
+pub const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+pub const ARGON2_PASSES: u32 = 3;
+pub const ARGON2_LANES: u32 = 1;
+
+pub fn new_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+pub fn password_key(password: &str, salt: &[u8]) -> VaultKey {
+    stretch(password, salt, ARGON2_MEMORY_KIB, ARGON2_PASSES, ARGON2_LANES).expect("Argon2id key derivation failed")
+}
+
+//Argon2id with the given cost, None when the cost or the salt are not accepted by Argon2
+pub fn stretch(password: &str, salt: &[u8], memory_kib: u32, passes: u32, lanes: u32) -> Option<VaultKey> {
+    let params = Params::new(memory_kib, passes, lanes, Some(32)).ok()?;
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+    let mut key = [0u8; 32];
+    argon2.hash_password_into(password.as_bytes(), salt, &mut key).ok()?;
+    VaultKey::from_bytes(&key)
+}
+
+//Recovery key printed as groups of 4 base32 characters, e.g. ABCD-EFGH-...
+pub fn new_recovery_key() -> String {
+    let mut bytes = [0u8; RECOVERY_KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let encoded = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &bytes);
+    encoded
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+//Accepts the recovery key with or without separators and in any case
+pub fn recovery_key(recovery: &str) -> Option<VaultKey> {
+    let normalized: String = recovery
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let bytes = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, &normalized)?;
+    if bytes.len() != RECOVERY_KEY_LEN {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(b"bioguard-recovery-key");
+    hasher.update(&bytes);
+    VaultKey::from_bytes(&hasher.finalize())
+}
+
+pub fn wrap(wrapping_key: &VaultKey, key: &VaultKey) -> Vec<u8> {
+    vault::encrypt(wrapping_key, "vault_key", key.as_bytes())
+}
+
+pub fn unwrap(wrapping_key: &VaultKey, wrapped: &[u8]) -> Option<VaultKey> {
+    vault::decrypt(wrapping_key, "vault_key", wrapped).and_then(|key| VaultKey::from_bytes(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_key_is_read_back_in_any_form() {
+        let printed = new_recovery_key();
+        assert_eq!(printed.len(), 32 + 7);
+        assert!(printed.split('-').all(|group| group.len() == 4));
+
+        let key = recovery_key(&printed).unwrap();
+        for typed in [printed.replace('-', ""), printed.to_lowercase(), printed.replace('-', " ")] {
+            assert_eq!(recovery_key(&typed).unwrap().as_bytes(), key.as_bytes(), "{}", typed);
+        }
+    }
+
+    #[test]
+    fn mistyped_recovery_key() {
+        let printed = new_recovery_key();
+        let vault_key = VaultKey::generate();
+        let wrapped = wrap(&recovery_key(&printed).unwrap(), &vault_key);
+
+        //Still valid base32 of the right length, only the unwrap tells it apart
+        let first = printed.chars().next().unwrap();
+        let mistyped = format!("{}{}", if first == 'A' { 'B' } else { 'A' }, &printed[1..]);
+        assert!(unwrap(&recovery_key(&mistyped).unwrap(), &wrapped).is_none());
+
+        //0, 1, 8 and 9 are not in the base32 alphabet, and a group too few or too many
+        assert!(recovery_key(&printed.replace(|c: char| c.is_ascii_uppercase(), "1")).is_none());
+        assert!(recovery_key(&printed[..printed.len() - 5]).is_none());
+        assert!(recovery_key(&format!("{}-ABCD", printed)).is_none());
+        assert!(recovery_key("").is_none());
+    }
+
+    #[test]
+    fn wrap_unwrap() {
+        let vault_key = VaultKey::generate();
+        let salt = new_salt();
+        //Small Argon2id cost, the cost does not change how the key is wrapped
+        let password = stretch("correct horse battery staple", &salt, 64, 1, 1).unwrap();
+        let wrapped = wrap(&password, &vault_key);
+        assert_eq!(unwrap(&password, &wrapped).unwrap().as_bytes(), vault_key.as_bytes());
+
+        let other = stretch("correct horse battery stapler", &salt, 64, 1, 1).unwrap();
+        assert!(unwrap(&other, &wrapped).is_none());
+        let mut tampered = wrapped.clone();
+        tampered[20] ^= 1;
+        assert!(unwrap(&password, &tampered).is_none());
+        assert!(unwrap(&password, &wrapped[..8]).is_none());
+
+        //A wrapped key is not a valid encrypted field of the vault
+        assert!(vault::decrypt(&password, "site_password", &wrapped).is_none());
+        assert!(stretch("password", &salt, 1, 1, 1).is_none());
+    }
+}
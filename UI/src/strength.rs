@@ -0,0 +1,597 @@
+//Password strength estimation in the spirit of zxcvbn. The password is covered by the
+//cheapest sequence of patterns an attacker would try first (common passwords and words,
+//keyboard walks, sequences, repeats, dates), what no pattern covers is brute forced.
+//The estimate is the number of guesses needed, computed in log10 to avoid overflows.
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//Short built-in list, a larger one sorted by popularity (one password per line, e.g. the 10k or
+//100k most common passwords of SecLists) is read from COMMON_PASSWORDS_PATH when set
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+const WORDLIST: &str = include_str!("wordlist.txt");
+
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 10000.0;
+const MIN_SUBMATCH_GUESSES_SINGLE_CHAR: f64 = 10.0;
+const MIN_SUBMATCH_GUESSES_MULTI_CHAR: f64 = 50.0;
+const MIN_YEAR_SPACE: f64 = 20.0;
+//Longer passwords are only estimated on their beginning, they are far past "very strong"
+const MAX_LENGTH: usize = 100;
+
+//Keyboard rows with the horizontal offset of their first key, in key widths
+const KEYBOARD: [(&str, &str, f64); 4] = [
+    ("`1234567890-=", "~!@#$%^&*()_+", 0.0),
+    ("qwertyuiop[]\\", "QWERTYUIOP{}|", 1.5),
+    ("asdfghjkl;'", "ASDFGHJKL:\"", 1.75),
+    ("zxcvbnm,./", "ZXCVBNM<>?", 2.25),
+];
+//Number of keys and their average number of neighbours, for the keyboard walk guesses
+const KEYBOARD_KEYS: f64 = 47.0;
+const KEYBOARD_DEGREE: f64 = 4.6;
+
+const L33T: [(char, char); 10] = [
+    ('4', 'a'),
+    ('@', 'a'),
+    ('3', 'e'),
+    ('1', 'i'),
+    ('!', 'i'),
+    ('0', 'o'),
+    ('$', 's'),
+    ('5', 's'),
+    ('7', 't'),
+    ('+', 't'),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Dictionary,
+    Spatial,
+    Sequence,
+    Repeat,
+    Date,
+    Bruteforce,
+}
+
+impl Pattern {
+    fn description(&self) -> &'static str {
+        match self {
+            Pattern::Dictionary => "common word",
+            Pattern::Spatial => "keyboard pattern",
+            Pattern::Sequence => "sequence",
+            Pattern::Repeat => "repetition",
+            Pattern::Date => "date",
+            Pattern::Bruteforce => "random characters",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Match {
+    start: usize,
+    //Inclusive
+    end: usize,
+    pattern: Pattern,
+    guesses_log10: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub guesses_log10: f64,
+    //0 (too guessable) to 4 (very unguessable), same thresholds as zxcvbn
+    pub score: u8,
+    //Patterns of the cheapest decomposition, in password order
+    pub patterns: Vec<Pattern>,
+}
+
+//Label of a score from 0 to 4
+pub fn label(score: u8) -> &'static str {
+    match score {
+        0 => "Very weak",
+        1 => "Weak",
+        2 => "Fair",
+        3 => "Strong",
+        _ => "Very strong",
+    }
+}
+
+impl Estimate {
+    pub fn label(&self) -> &'static str {
+        label(self.score)
+    }
+
+    //e.g. "Weak, 10^5.3 guesses (common word, date)"
+    pub fn summary(&self) -> String {
+        let mut found: Vec<&str> = Vec::new();
+        for pattern in &self.patterns {
+            if *pattern != Pattern::Bruteforce && !found.contains(&pattern.description()) {
+                found.push(pattern.description());
+            }
+        }
+        if found.is_empty() {
+            format!("{}, 10^{:.1} guesses", self.label(), self.guesses_log10)
+        } else {
+            format!("{}, 10^{:.1} guesses ({})", self.label(), self.guesses_log10, found.join(", "))
+        }
+    }
+}
+
+//Ranked list of common passwords read from COMMON_PASSWORDS_PATH, None when not configured or readable
+fn common_passwords_file() -> Option<String> {
+    let path = env::var("COMMON_PASSWORDS_PATH").ok().filter(|path| !path.is_empty())?;
+    fs::read_to_string(&path).map_err(|e| eprintln!("Failed to read the common passwords {}: {}", path, e)).ok()
+}
+
+//Rank of every known word, the common passwords first by popularity
+fn dictionary() -> &'static HashMap<String, usize> {
+    static DICTIONARY: OnceLock<HashMap<String, usize>> = OnceLock::new();
+    DICTIONARY.get_or_init(|| {
+        let mut ranks = HashMap::new();
+        let file = common_passwords_file().unwrap_or_default();
+        //Built-in entries missing from the configured file come after it
+        for (rank, password) in file.lines().chain(COMMON_PASSWORDS.lines()).map(|line| line.trim()).filter(|line| !line.is_empty()).enumerate() {
+            ranks.entry(password.to_lowercase()).or_insert(rank + 1);
+        }
+        //The wordlist is not sorted by frequency, every word gets the rank of the list size
+        let words: Vec<&str> = WORDLIST.lines().filter(|line| !line.is_empty()).collect();
+        for word in &words {
+            ranks.entry(word.to_string()).or_insert(words.len());
+        }
+        ranks
+    })
+}
+
+fn log10_binomial(n: usize, k: usize) -> f64 {
+    (0..k).map(|i| ((n - i) as f64).log10() - ((i + 1) as f64).log10()).sum()
+}
+
+//Guesses of the capitalisation: none, first or last letter or all caps are cheap
+fn uppercase_variations(token: &[char]) -> f64 {
+    let upper = token.iter().filter(|c| c.is_uppercase()).count();
+    let lower = token.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 0.0;
+    }
+    let first_only = token[0].is_uppercase() && upper == 1;
+    let last_only = token[token.len() - 1].is_uppercase() && upper == 1;
+    if lower == 0 || first_only || last_only {
+        return 2f64.log10();
+    }
+    let variations: f64 = (1..=upper.min(lower)).map(|i| 10f64.powf(log10_binomial(upper + lower, i))).sum();
+    variations.log10()
+}
+
+fn match_dictionary(chars: &[char], matches: &mut Vec<Match>) {
+    let dictionary = dictionary();
+    let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    if lower.len() != chars.len() {
+        return;
+    }
+    //The digit 1 reads as i or l
+    let unleet = |one: char| -> Vec<char> {
+        lower
+            .iter()
+            .map(|&c| match c {
+                '1' => one,
+                c => L33T.iter().find(|(leet, _)| *leet == c).map(|(_, letter)| *letter).unwrap_or(c),
+            })
+            .collect()
+    };
+    let candidates = [(lower.clone(), false), (unleet('i'), true), (unleet('l'), true)];
+
+    let n = chars.len();
+    for start in 0..n {
+        for end in start + 2..n {
+            let token = &chars[start..=end];
+            for (text, leet) in &candidates {
+                let word: String = text[start..=end].iter().collect();
+                let reversed: String = word.chars().rev().collect();
+                let substitutions = if *leet {
+                    token.iter().zip(&lower[start..=end]).filter(|(c, _)| L33T.iter().any(|(leet, _)| leet == *c)).count()
+                } else {
+                    0
+                };
+                if *leet && substitutions == 0 {
+                    continue;
+                }
+                for (candidate, reversed_factor) in [(&word, 0.0), (&reversed, 2f64.log10())] {
+                    if let Some(&rank) = dictionary.get(candidate.as_str()) {
+                        let guesses_log10 = (rank as f64).log10()
+                            + uppercase_variations(token)
+                            + substitutions as f64 * 2f64.log10()
+                            + reversed_factor;
+                        matches.push(Match { start, end, pattern: Pattern::Dictionary, guesses_log10 });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn key_position(c: char) -> Option<(usize, f64, bool)> {
+    KEYBOARD.iter().enumerate().find_map(|(row, (keys, shifted, offset))| {
+        if let Some(column) = keys.chars().position(|key| key == c) {
+            Some((row, column as f64 + offset, false))
+        } else {
+            shifted.chars().position(|key| key == c).map(|column| (row, column as f64 + offset, true))
+        }
+    })
+}
+
+fn adjacent(a: (usize, f64), b: (usize, f64)) -> bool {
+    let rows = (a.0 as i32 - b.0 as i32).abs();
+    let distance = (a.1 - b.1).abs();
+    (rows == 0 && (distance - 1.0).abs() < 1e-9) || (rows == 1 && distance <= 0.75 + 1e-9)
+}
+
+fn spatial_guesses(length: usize, turns: usize, shifted: usize) -> f64 {
+    let mut guesses = 0.0;
+    for i in 2..=length {
+        for j in 1..=turns.min(i - 1) {
+            guesses += 10f64.powf(log10_binomial(i - 1, j - 1)) * KEYBOARD_KEYS * KEYBOARD_DEGREE.powi(j as i32);
+        }
+    }
+    let unshifted = length - shifted;
+    let shift_variations = if shifted == 0 {
+        0.0
+    } else if unshifted == 0 {
+        2f64.log10()
+    } else {
+        (1..=shifted.min(unshifted)).map(|i| 10f64.powf(log10_binomial(length, i))).sum::<f64>().log10()
+    };
+    guesses.log10() + shift_variations
+}
+
+//Runs of at least 3 neighbouring keys, e.g. qwerty or zaq1
+fn match_spatial(chars: &[char], matches: &mut Vec<Match>) {
+    let positions: Vec<Option<(usize, f64, bool)>> = chars.iter().map(|&c| key_position(c)).collect();
+    let n = chars.len();
+    let mut start = 0;
+    while start < n {
+        let mut end = start;
+        let mut turns = 0;
+        let mut last_direction = None;
+        while end + 1 < n {
+            match (positions[end], positions[end + 1]) {
+                (Some(a), Some(b)) if adjacent((a.0, a.1), (b.0, b.1)) => {
+                    let direction = (b.0 as i32 - a.0 as i32, (b.1 - a.1).signum() as i32);
+                    if last_direction != Some(direction) {
+                        turns += 1;
+                        last_direction = Some(direction);
+                    }
+                    end += 1;
+                }
+                _ => break,
+            }
+        }
+        if end - start + 1 >= 3 {
+            let shifted = positions[start..=end].iter().filter(|position| position.map(|p| p.2).unwrap_or(false)).count();
+            matches.push(Match {
+                start,
+                end,
+                pattern: Pattern::Spatial,
+                guesses_log10: spatial_guesses(end - start + 1, turns, shifted),
+            });
+        }
+        start = end.max(start) + 1;
+    }
+}
+
+//Runs of at least 3 characters with a constant step, e.g. abcd, 2468 or 9876
+fn match_sequence(chars: &[char], matches: &mut Vec<Match>) {
+    let n = chars.len();
+    let class = |c: char| if c.is_ascii_lowercase() { 0 } else if c.is_ascii_uppercase() { 1 } else if c.is_ascii_digit() { 2 } else { 3 };
+    let mut start = 0;
+    while start + 2 < n {
+        let step = chars[start + 1] as i64 - chars[start] as i64;
+        let mut end = start + 1;
+        if step != 0 && step.abs() <= 5 && class(chars[start]) < 3 && class(chars[start]) == class(chars[end]) {
+            while end + 1 < n && chars[end + 1] as i64 - chars[end] as i64 == step && class(chars[end + 1]) == class(chars[start]) {
+                end += 1;
+            }
+        }
+        if end - start + 1 >= 3 {
+            let first = chars[start];
+            let base: f64 = if "aAzZ019".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let direction = if step > 0 { 1.0 } else { 2.0 };
+            matches.push(Match {
+                start,
+                end,
+                pattern: Pattern::Sequence,
+                guesses_log10: (base * direction * (end - start + 1) as f64).log10(),
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+//A unit repeated several times, e.g. aaaa or abcabc, costs the unit times the count.
+//The shortest unit is taken and the search continues after the repetition.
+fn match_repeat(chars: &[char], matches: &mut Vec<Match>) {
+    let n = chars.len();
+    let mut start = 0;
+    while start < n {
+        let repeat = (1..=(n - start) / 2).find_map(|unit| {
+            let mut count = 1;
+            while start + (count + 1) * unit <= n
+                && chars[start + count * unit..start + (count + 1) * unit] == chars[start..start + unit]
+            {
+                count += 1;
+            }
+            if count >= 2 && (unit > 1 || count >= 3) {
+                Some((unit, count))
+            } else {
+                None
+            }
+        });
+        match repeat {
+            Some((unit, count)) => {
+                let base = most_guessable(&chars[start..start + unit]).0;
+                matches.push(Match {
+                    start,
+                    end: start + count * unit - 1,
+                    pattern: Pattern::Repeat,
+                    guesses_log10: base + (count as f64).log10(),
+                });
+                start += count * unit;
+            }
+            None => start += 1,
+        }
+    }
+}
+
+fn current_year() -> i32 {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    1970 + (seconds / 31_556_952) as i32
+}
+
+fn valid_date(day: u32, month: u32, year: i32) -> bool {
+    (1..=31).contains(&day) && (1..=12).contains(&month) && (1000..=2050).contains(&year)
+}
+
+fn expand_year(year: u32, digits: usize) -> i32 {
+    match digits {
+        2 if year > 50 => 1900 + year as i32,
+        2 => 2000 + year as i32,
+        _ => year as i32,
+    }
+}
+
+//Reads day, month and year in any usual order from the parts of a date
+fn parse_date(parts: &[&str]) -> Option<i32> {
+    let numbers: Vec<u32> = parts.iter().map(|part| part.parse().ok()).collect::<Option<Vec<u32>>>()?;
+    //(day, month, year) indexes: d-m-y, m-d-y, y-m-d, y-d-m
+    let orders: [(usize, usize, usize); 4] = [(0, 1, 2), (1, 0, 2), (2, 1, 0), (1, 2, 0)];
+    orders.iter().find_map(|&(day, month, year)| {
+        let year_digits = parts[year].len();
+        if year_digits != 2 && year_digits != 4 {
+            return None;
+        }
+        let full_year = expand_year(numbers[year], year_digits);
+        if valid_date(numbers[day], numbers[month], full_year) {
+            Some(full_year)
+        } else {
+            None
+        }
+    })
+}
+
+fn match_date(chars: &[char], matches: &mut Vec<Match>) {
+    let n = chars.len();
+    let reference = current_year();
+    let year_guesses = |year: i32| ((year - reference).abs() as f64).max(MIN_YEAR_SPACE);
+    for start in 0..n {
+        for end in start + 3..n.min(start + 10) {
+            let token: String = chars[start..=end].iter().collect();
+            let year = if token.chars().all(|c| c.is_ascii_digit()) {
+                //Without separators: a recent year alone, or d/m/y with 1 or 2 digits day and month
+                if token.len() == 4 && (1900..=2050).contains(&token.parse::<i32>().unwrap()) {
+                    let year = token.parse::<i32>().unwrap();
+                    matches.push(Match {
+                        start,
+                        end,
+                        pattern: Pattern::Date,
+                        guesses_log10: year_guesses(year).log10(),
+                    });
+                    continue;
+                }
+                let splits: Vec<(usize, usize)> = match token.len() {
+                    4 => vec![(1, 2), (2, 3)],
+                    5 => vec![(1, 3), (2, 3)],
+                    6 => vec![(1, 2), (2, 4), (4, 5)],
+                    7 => vec![(1, 3), (2, 3), (4, 5), (4, 6)],
+                    8 => vec![(2, 4), (4, 6)],
+                    _ => vec![],
+                };
+                splits.iter().find_map(|&(first, second)| {
+                    parse_date(&[&token[..first], &token[first..second], &token[second..]])
+                }).map(|year| (year, false))
+            } else {
+                //With the same separator twice
+                let separator = match token.chars().find(|c| !c.is_ascii_digit()) {
+                    Some(separator) if " -/._".contains(separator) => separator,
+                    _ => continue,
+                };
+                let parts: Vec<&str> = token.split(separator).collect();
+                if parts.len() != 3 || parts.iter().any(|part| part.is_empty() || part.len() > 4) {
+                    continue;
+                }
+                parse_date(&parts).map(|year| (year, true))
+            };
+            if let Some((year, separator)) = year {
+                let separator_factor = if separator { 4f64.log10() } else { 0.0 };
+                matches.push(Match {
+                    start,
+                    end,
+                    pattern: Pattern::Date,
+                    guesses_log10: (365.0 * year_guesses(year)).log10() + separator_factor,
+                });
+            }
+        }
+    }
+}
+
+fn bruteforce_guesses(length: usize) -> f64 {
+    let min = if length == 1 { MIN_SUBMATCH_GUESSES_SINGLE_CHAR } else { MIN_SUBMATCH_GUESSES_MULTI_CHAR };
+    (length as f64 * BRUTEFORCE_CARDINALITY.log10()).max((min + 1.0).log10())
+}
+
+//log10(10^a + 10^b)
+fn log10_add(a: f64, b: f64) -> f64 {
+    let (high, low) = if a > b { (a, b) } else { (b, a) };
+    high + (1.0 + 10f64.powf(low - high)).log10()
+}
+
+//Cheapest cover of the password by the matches and brute forced gaps, like zxcvbn:
+//guesses = l! * product of the match guesses + MIN_GUESSES_BEFORE_GROWING_SEQUENCE^(l - 1)
+fn most_guessable(chars: &[char]) -> (f64, Vec<Pattern>) {
+    let n = chars.len();
+    if n == 0 {
+        return (0.0, Vec::new());
+    }
+
+    let mut matches = Vec::new();
+    match_dictionary(chars, &mut matches);
+    match_spatial(chars, &mut matches);
+    match_sequence(chars, &mut matches);
+    match_repeat(chars, &mut matches);
+    match_date(chars, &mut matches);
+    for start in 0..n {
+        for end in start..n {
+            matches.push(Match { start, end, pattern: Pattern::Bruteforce, guesses_log10: bruteforce_guesses(end - start + 1) });
+        }
+    }
+    for found in matches.iter_mut() {
+        if found.pattern != Pattern::Bruteforce {
+            let min = if found.end == found.start { MIN_SUBMATCH_GUESSES_SINGLE_CHAR } else { MIN_SUBMATCH_GUESSES_MULTI_CHAR };
+            found.guesses_log10 = found.guesses_log10.max(min.log10());
+        }
+    }
+
+    let mut by_end: Vec<Vec<&Match>> = vec![Vec::new(); n];
+    for found in &matches {
+        by_end[found.end].push(found);
+    }
+
+    //best[k][l]: lowest log10 product covering chars[..=k] with l matches, and the last match
+    let mut best: Vec<Vec<Option<(f64, &Match)>>> = vec![vec![None; n + 1]; n];
+    for k in 0..n {
+        for &found in &by_end[k] {
+            if found.start == 0 {
+                if best[k][1].map(|(cost, _)| found.guesses_log10 < cost).unwrap_or(true) {
+                    best[k][1] = Some((found.guesses_log10, found));
+                }
+                continue;
+            }
+            for l in 1..n {
+                if let Some((cost, _)) = best[found.start - 1][l] {
+                    let total = cost + found.guesses_log10;
+                    if best[k][l + 1].map(|(current, _)| total < current).unwrap_or(true) {
+                        best[k][l + 1] = Some((total, found));
+                    }
+                }
+            }
+        }
+    }
+
+    let log10_factorial = |l: usize| (1..=l).map(|i| (i as f64).log10()).sum::<f64>();
+    let (count, guesses_log10) = (1..=n)
+        .filter_map(|l| best[n - 1][l].map(|(product, _)| {
+            let sequence = log10_factorial(l) + product;
+            let total = if l > 1 { log10_add(sequence, MIN_GUESSES_BEFORE_GROWING_SEQUENCE.log10() * (l - 1) as f64) } else { sequence };
+            (l, total)
+        }))
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .unwrap();
+
+    let mut patterns = Vec::new();
+    let (mut k, mut l) = (n - 1, count);
+    while l > 0 {
+        let (_, found) = best[k][l].unwrap();
+        patterns.push(found.pattern);
+        if found.start == 0 {
+            break;
+        }
+        k = found.start - 1;
+        l -= 1;
+    }
+    patterns.reverse();
+
+    (guesses_log10, patterns)
+}
+
+pub fn estimate(password: &str) -> Estimate {
+    let chars: Vec<char> = password.chars().take(MAX_LENGTH).collect();
+    let (guesses_log10, patterns) = most_guessable(&chars);
+    let guesses = 10f64.powf(guesses_log10);
+    let score = if guesses < 1e3 + 5.0 {
+        0
+    } else if guesses < 1e6 + 5.0 {
+        1
+    } else if guesses < 1e8 + 5.0 {
+        2
+    } else if guesses < 1e10 + 5.0 {
+        3
+    } else {
+        4
+    };
+    Estimate { guesses_log10, score, patterns }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_pattern(password: &str, pattern: Pattern) -> Estimate {
+        let estimate = estimate(password);
+        assert_eq!(estimate.patterns, vec![pattern], "{}", password);
+        estimate
+    }
+
+    #[test]
+    fn keyboard_walks() {
+        for password in ["tyuiop[]", "xcvbnm,./", "wsxcde", "GHJKL:"] {
+            assert!(single_pattern(password, Pattern::Spatial).score <= 1, "{}", password);
+        }
+    }
+
+    #[test]
+    fn dates() {
+        for password in ["13/05/1991", "1991-05-13", "13051991", "130591"] {
+            assert!(single_pattern(password, Pattern::Date).score <= 1, "{}", password);
+        }
+    }
+
+    #[test]
+    fn repeats() {
+        for password in ["aaaaaaaaaa", "abcabcabcabc", "xyz!xyz!xyz!"] {
+            assert!(single_pattern(password, Pattern::Repeat).score <= 1, "{}", password);
+        }
+    }
+
+    #[test]
+    fn l33t_substitutions() {
+        for password in ["p@ssw0rd", "P4$$w0rd", "m0nk3y", "l3tm31n"] {
+            assert_eq!(single_pattern(password, Pattern::Dictionary).score, 0, "{}", password);
+        }
+        //Each substitution doubles the guesses of the word
+        assert!(estimate("l3tm31n").guesses_log10 > estimate("letmein").guesses_log10);
+    }
+
+    #[test]
+    fn random_passwords_are_very_strong() {
+        for password in ["8^+jhAUW&w!]}V,cy/*5", "Bekecuhecimujureba17x"] {
+            assert_eq!(estimate(password).score, 4, "{}", password);
+        }
+    }
+}
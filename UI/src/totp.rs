@@ -0,0 +1,294 @@
+//One-time passwords of the 2FA secrets attached to credentials: HOTP (RFC 4226) and
+//TOTP (RFC 6238). The secret is given as an otpauth:// URI, as exported by most
+//authenticator apps, or as a bare base32 secret which is read as a default TOTP.
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use crate::crypto::hmac;
+
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Totp { period: u64 },
+    //The counter is the one of the next code to use
+    Hotp { counter: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtpAuth {
+    pub kind: Kind,
+    pub secret: Vec<u8>,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub label: String,
+    pub issuer: String,
+}
+
+pub fn hotp(secret: &[u8], counter: u64, digits: u32, algorithm: Algorithm) -> String {
+    let message = counter.to_be_bytes();
+    let mac = match algorithm {
+        Algorithm::Sha1 => hmac::<Sha1>(secret, &message),
+        Algorithm::Sha256 => hmac::<Sha256>(secret, &message),
+        Algorithm::Sha512 => hmac::<Sha512>(secret, &message),
+    };
+
+    //Dynamic truncation
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([mac[offset] & 0x7f, mac[offset + 1], mac[offset + 2], mac[offset + 3]]);
+    let code = binary as u64 % 10u64.pow(digits);
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+pub fn totp(secret: &[u8], time: u64, period: u64, digits: u32, algorithm: Algorithm) -> String {
+    hotp(secret, time / period, digits, algorithm)
+}
+
+fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    let normalized: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=' && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    base32::decode(base32::Alphabet::Rfc4648 { padding: false }, &normalized).filter(|bytes| !bytes.is_empty())
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+//Reads an otpauth://totp/... or otpauth://hotp/... URI, or a bare base32 secret
+pub fn parse(input: &str) -> Option<OtpAuth> {
+    let input = input.trim();
+    let rest = match input.strip_prefix("otpauth://") {
+        Some(rest) => rest,
+        None => {
+            return Some(OtpAuth {
+                kind: Kind::Totp { period: DEFAULT_PERIOD },
+                secret: decode_secret(input)?,
+                algorithm: Algorithm::Sha1,
+                digits: DEFAULT_DIGITS,
+                label: String::new(),
+                issuer: String::new(),
+            })
+        }
+    };
+
+    let (path, query) = rest.split_once('?')?;
+    let (kind, label) = path.split_once('/').unwrap_or((path, ""));
+
+    let mut secret = None;
+    let mut algorithm = Algorithm::Sha1;
+    let mut digits = DEFAULT_DIGITS;
+    let mut period = DEFAULT_PERIOD;
+    let mut counter = None;
+    let mut issuer = String::new();
+    for pair in query.split('&') {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        match name.to_ascii_lowercase().as_str() {
+            "secret" => secret = decode_secret(&value),
+            "algorithm" => {
+                algorithm = match value.to_ascii_uppercase().as_str() {
+                    "SHA1" => Algorithm::Sha1,
+                    "SHA256" => Algorithm::Sha256,
+                    "SHA512" => Algorithm::Sha512,
+                    _ => return None,
+                }
+            }
+            "digits" => digits = value.parse().ok().filter(|digits| (6..=8).contains(digits))?,
+            "period" => period = value.parse().ok().filter(|&period| period > 0)?,
+            "counter" => counter = Some(value.parse().ok()?),
+            "issuer" => issuer = value,
+            _ => {}
+        }
+    }
+
+    let kind = match kind.to_ascii_lowercase().as_str() {
+        "totp" => Kind::Totp { period },
+        "hotp" => Kind::Hotp { counter: counter? },
+        _ => return None,
+    };
+
+    Some(OtpAuth {
+        kind,
+        secret: secret?,
+        algorithm,
+        digits,
+        label: percent_decode(label),
+        issuer,
+    })
+}
+
+impl OtpAuth {
+    pub fn code(&self, now: u64) -> String {
+        match self.kind {
+            Kind::Totp { period } => totp(&self.secret, now, period, self.digits, self.algorithm),
+            Kind::Hotp { counter } => hotp(&self.secret, counter, self.digits, self.algorithm),
+        }
+    }
+
+    //Seconds before the code changes, None for HOTP which only changes once used
+    pub fn remaining(&self, now: u64) -> Option<u64> {
+        match self.kind {
+            Kind::Totp { period } => Some(period - now % period),
+            Kind::Hotp { .. } => None,
+        }
+    }
+
+    //Code split in two groups with its countdown, shown in the credentials table
+    pub fn display(&self, now: u64) -> String {
+        let code = self.code(now);
+        let (first, second) = code.split_at(code.len() / 2);
+        match self.remaining(now) {
+            Some(remaining) => format!("{} {} ({}s)", first, second, remaining),
+            None => format!("{} {}", first, second),
+        }
+    }
+
+    //Same secret with the counter moved past the code just used
+    pub fn next(&self) -> OtpAuth {
+        let mut next = self.clone();
+        if let Kind::Hotp { counter } = self.kind {
+            next.kind = Kind::Hotp { counter: counter + 1 };
+        }
+        next
+    }
+
+    pub fn to_uri(&self) -> String {
+        let secret = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &self.secret);
+        let (kind, parameter) = match self.kind {
+            Kind::Totp { period } => ("totp", format!("period={}", period)),
+            Kind::Hotp { counter } => ("hotp", format!("counter={}", counter)),
+        };
+        let mut uri = format!(
+            "otpauth://{}/{}?secret={}&algorithm={}&digits={}&{}",
+            kind,
+            percent_encode(&self.label),
+            secret,
+            self.algorithm.as_str(),
+            self.digits,
+            parameter
+        );
+        if !self.issuer.is_empty() {
+            uri.push_str(&format!("&issuer={}", percent_encode(&self.issuer)));
+        }
+        uri
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED_SHA1: &[u8] = b"12345678901234567890";
+    const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    //RFC 6238 appendix B
+    #[test]
+    fn rfc6238_vectors() {
+        let vectors: [(u64, &str, &str, &str); 6] = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, sha1, sha256, sha512) in vectors {
+            assert_eq!(totp(SEED_SHA1, time, 30, 8, Algorithm::Sha1), sha1, "SHA1 at {}", time);
+            assert_eq!(totp(SEED_SHA256, time, 30, 8, Algorithm::Sha256), sha256, "SHA256 at {}", time);
+            assert_eq!(totp(SEED_SHA512, time, 30, 8, Algorithm::Sha512), sha512, "SHA512 at {}", time);
+        }
+    }
+
+    //RFC 4226 appendix D
+    #[test]
+    fn rfc4226_vectors() {
+        let codes = ["755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489"];
+        for (counter, code) in codes.iter().enumerate() {
+            assert_eq!(hotp(SEED_SHA1, counter as u64, 6, Algorithm::Sha1), *code);
+        }
+    }
+
+    #[test]
+    fn parses_otpauth_uri() {
+        let otp = parse("otpauth://totp/ACME%20Co:john@example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=ACME%20Co&algorithm=SHA1&digits=8&period=30").unwrap();
+        assert_eq!(otp.secret, SEED_SHA1);
+        assert_eq!(otp.digits, 8);
+        assert_eq!(otp.kind, Kind::Totp { period: 30 });
+        assert_eq!(otp.label, "ACME Co:john@example.com");
+        assert_eq!(otp.issuer, "ACME Co");
+        assert_eq!(otp.code(59), "94287082");
+        assert_eq!(parse(&otp.to_uri()), Some(otp));
+    }
+
+    #[test]
+    fn hotp_needs_counter_and_advances() {
+        assert_eq!(parse("otpauth://hotp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"), None);
+        let otp = parse("otpauth://hotp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1").unwrap();
+        assert_eq!(otp.code(0), "287082");
+        assert_eq!(otp.next().code(0), "359152");
+        assert_eq!(otp.remaining(0), None);
+    }
+
+    #[test]
+    fn bare_secret_is_default_totp() {
+        let otp = parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(otp.kind, Kind::Totp { period: 30 });
+        assert_eq!(otp.digits, 6);
+        assert_eq!(otp.display(59), "287 082 (1s)");
+    }
+}
@@ -0,0 +1,127 @@
+//Authenticated encryption of the vault content with AES-256-GCM.
+//Every value is stored as nonce || ciphertext, the name of the field is used as associated
+//data so an encrypted password cannot be swapped with another column of the row.
+//The user is not part of it: every user has their own random vault key, so a value moved to
+//another user's row does not decrypt. The row is not part of it either: its id is only known
+//after the insert, and someone who can write the database can already delete rows or put back
+//an older copy of the file. Moving a value between two rows of the same user stays possible,
+//reading or forging one does not.
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use rand::RngCore;
+use zeroize::Zeroize;
+
+const NONCE_LEN: usize = 12;
+
+pub struct VaultKey([u8; 32]);
+
+impl VaultKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        VaultKey(key)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let key: [u8; 32] = bytes.try_into().ok()?;
+        Some(VaultKey(key))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl Drop for VaultKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+//Never print the key, AppState derives Debug
+impl std::fmt::Debug for VaultKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VaultKey(..)")
+    }
+}
+
+pub fn encrypt(key: &VaultKey, field: &str, plaintext: &[u8]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(key.as_bytes().into());
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: field.as_bytes() })
+        .expect("AES-GCM encryption failed");
+
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&ciphertext);
+    blob
+}
+
+pub fn decrypt(key: &VaultKey, field: &str, blob: &[u8]) -> Option<Vec<u8>> {
+    if blob.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(key.as_bytes().into());
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: field.as_bytes() })
+        .ok()
+}
+
+pub fn encrypt_str(key: &VaultKey, field: &str, plaintext: &str) -> Vec<u8> {
+    encrypt(key, field, plaintext.as_bytes())
+}
+
+pub fn decrypt_str(key: &VaultKey, field: &str, blob: &[u8]) -> Option<String> {
+    decrypt(key, field, blob).and_then(|plaintext| String::from_utf8(plaintext).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_with_fresh_nonces() {
+        let key = VaultKey::generate();
+        let first = encrypt_str(&key, "site_password", "correct horse");
+        let second = encrypt_str(&key, "site_password", "correct horse");
+        assert_ne!(first[..NONCE_LEN], second[..NONCE_LEN]);
+        assert_eq!(first.len(), NONCE_LEN + "correct horse".len() + 16);
+        assert_eq!(decrypt_str(&key, "site_password", &first).unwrap(), "correct horse");
+        assert_eq!(decrypt_str(&key, "site_password", &second).unwrap(), "correct horse");
+        assert_eq!(decrypt(&key, "notes", &encrypt(&key, "notes", b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn other_field_or_key_is_refused() {
+        let key = VaultKey::generate();
+        let blob = encrypt_str(&key, "site_password", "correct horse");
+        assert!(decrypt(&key, "site_username", &blob).is_none());
+        assert!(decrypt(&VaultKey::generate(), "site_password", &blob).is_none());
+    }
+
+    #[test]
+    fn tampered_blob_is_refused() {
+        let key = VaultKey::generate();
+        let blob = encrypt_str(&key, "site_password", "correct horse");
+        //Nonce, ciphertext and tag
+        for offset in [0, NONCE_LEN, blob.len() - 1] {
+            let mut tampered = blob.clone();
+            tampered[offset] ^= 1;
+            assert!(decrypt(&key, "site_password", &tampered).is_none(), "offset {}", offset);
+        }
+        assert!(decrypt(&key, "site_password", &blob[..blob.len() - 1]).is_none());
+        assert!(decrypt(&key, "site_password", &blob[..NONCE_LEN - 1]).is_none());
+        assert!(decrypt(&key, "site_password", &[]).is_none());
+    }
+
+    #[test]
+    fn invalid_utf8_is_not_a_string() {
+        let key = VaultKey::generate();
+        assert!(decrypt_str(&key, "notes", &encrypt(&key, "notes", &[0xff, 0xfe])).is_none());
+        assert!(VaultKey::from_bytes(&[0u8; 31]).is_none());
+        assert_eq!(format!("{:?}", key), "VaultKey(..)");
+    }
+}